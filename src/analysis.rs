@@ -1,9 +1,14 @@
 //! This module contains the committer coverage analysis.
 use super::{
-    coverage::CoverageProvider,
-    git::{BlameLine, BlameProvider},
+    coverage::{CoverageProvider, FileCoverage},
+    git::{working_tree::UNCOMMITTED_COMMIT_ID, BlameFile, BlameLine, BlameProvider},
+};
+use glob::{MatchOptions, Pattern};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
-use std::collections::{BTreeMap, HashMap};
 
 /// Represents the summary of the coverage for all committers.
 /// This will be printed to the pull request as a comment.
@@ -13,6 +18,20 @@ pub struct CommitterCoverageSummary {
     covered: u32,
     percent_covered: f32,
     user_stats: HashMap<String, CommitterCoverageUserStat>,
+    /// Maps file path to the coverage lines joined with their blame.
+    /// Only collected if needed, see `AttributionOptions::collect_lines`.
+    file_lines: BTreeMap<String, Vec<AttributedLine>>,
+    /// Maps each committer email to one of the commits blamed to them.
+    commits: BTreeMap<String, String>,
+    /// Whether the blame only saw part of the history, e.g. a shallow clone,
+    /// so lines may be attributed to the wrong committer.
+    partial_history: bool,
+    /// The coverage weighted by the age of the lines, if enabled.
+    recency_weighted: Option<WeightedCoverage>,
+    /// Maps file path to the coverage of the lines without a blame,
+    /// e.g. from stale coverage or files not committed. These lines
+    /// are not in the totals.
+    unattributed: BTreeMap<String, CommitterCoverageFileStat>,
 }
 
 impl CommitterCoverageSummary {
@@ -29,12 +48,19 @@ impl CommitterCoverageSummary {
         let user_stat = self.user_stats.get_mut(email).unwrap();
         user_stat.lines = 0;
         user_stat.covered = 0;
+        user_stat.files.clear();
 
         Ok(())
     }
 
-    /// This function increments the line coverage for a user.
-    pub fn incr_user_line_cover(&mut self, email: &str, covered: bool) {
+    /// This function increments the line coverage for a user
+    /// and for the file the line belongs to.
+    pub fn incr_user_line_cover(
+        &mut self,
+        email: &str,
+        path: &str,
+        covered: bool,
+    ) {
         self.lines += 1;
         let covered = if covered { 1 } else { 0 };
         self.covered += covered;
@@ -44,6 +70,56 @@ impl CommitterCoverageSummary {
         stat.lines += 1;
         stat.covered += covered;
         stat.percent_covered = stat.covered as f32 / stat.lines as f32 * 100.0;
+
+        let file_stat = stat
+            .files
+            .entry(path.to_string())
+            .or_insert_with(|| CommitterCoverageFileStat::new(path, 0, 0));
+        file_stat.lines += 1;
+        file_stat.covered += covered;
+    }
+
+    /// Adds the weight of a line to the recency-weighted coverage
+    /// of the summary and of the user, see `recency_weight`.
+    pub fn incr_user_line_weight(&mut self, email: &str, weight: f64, covered: bool) {
+        self.recency_weighted
+            .get_or_insert_with(WeightedCoverage::default)
+            .add(weight, covered);
+        let stat = self.user_stats.get_mut(email).unwrap();
+        stat.recency_weighted
+            .get_or_insert_with(WeightedCoverage::default)
+            .add(weight, covered);
+    }
+
+    /// Counts a coverage line that could not be attributed to a committer.
+    pub fn incr_unattributed_line(&mut self, path: &str, covered: bool) {
+        let file_stat = self
+            .unattributed
+            .entry(path.to_string())
+            .or_insert_with(|| CommitterCoverageFileStat::new(path, 0, 0));
+        file_stat.lines += 1;
+        if covered {
+            file_stat.covered += 1;
+        }
+    }
+
+    /// Adds the unattributed lines of a file, e.g. when loading from a report.
+    pub fn add_unattributed_file_stat(&mut self, file_stat: CommitterCoverageFileStat) {
+        self.unattributed.insert(file_stat.path.clone(), file_stat);
+    }
+
+    pub fn get_unattributed_files(&self) -> &BTreeMap<String, CommitterCoverageFileStat> {
+        &self.unattributed
+    }
+
+    /// Returns the sum of the unattributed lines of all files.
+    pub fn get_unattributed(&self) -> CommitterCoverageFileStat {
+        let mut total = CommitterCoverageFileStat::new("", 0, 0);
+        for file_stat in self.unattributed.values() {
+            total.lines += file_stat.lines;
+            total.covered += file_stat.covered;
+        }
+        total
     }
 
     pub fn create_user_stat_if_not_exists(&mut self, email: &str, name: Option<String>) {
@@ -69,10 +145,33 @@ impl CommitterCoverageSummary {
         let stat = self.user_stats.get_mut(email).unwrap();
         stat.lines = lines;
         stat.covered = covered;
+        stat.percent_covered = percent(covered, lines);
 
         Ok(())
     }
 
+    /// Creates a summary from existing user stats, e.g. loaded from a report.
+    /// The totals are the sum of the user stats.
+    pub fn from_user_stats(
+        user_stats: Vec<CommitterCoverageUserStat>,
+    ) -> CommitterCoverageSummary {
+        let mut summary = CommitterCoverageSummary::default();
+        for stat in user_stats {
+            summary.lines += stat.lines;
+            summary.covered += stat.covered;
+            if let Some(weighted) = &stat.recency_weighted {
+                let total = summary
+                    .recency_weighted
+                    .get_or_insert_with(WeightedCoverage::default);
+                total.lines += weighted.lines;
+                total.covered += weighted.covered;
+            }
+            summary.user_stats.insert(stat.email.clone(), stat);
+        }
+        summary.percent_covered = percent(summary.covered, summary.lines);
+        summary
+    }
+
     pub fn get_lines(&self) -> u32 {
         self.lines
     }
@@ -84,6 +183,73 @@ impl CommitterCoverageSummary {
     pub fn get_percent_covered(&self) -> f32 {
         self.percent_covered
     }
+
+    pub fn get_recency_weighted(&self) -> &Option<WeightedCoverage> {
+        &self.recency_weighted
+    }
+
+    pub fn set_recency_weighted(&mut self, recency_weighted: Option<WeightedCoverage>) {
+        self.recency_weighted = recency_weighted;
+    }
+
+    pub fn set_partial_history(&mut self, partial_history: bool) {
+        self.partial_history = partial_history;
+    }
+
+    pub fn is_partial_history(&self) -> bool {
+        self.partial_history
+    }
+
+    /// Records the committer a coverage line is attributed to.
+    /// This does not change the stats, see `incr_user_line_cover`.
+    pub fn add_attributed_line(
+        &mut self,
+        path: &str,
+        line: u32,
+        commit: &str,
+        email: &str,
+        covered: bool,
+    ) {
+        self.add_user_commit(email, commit);
+        self.file_lines
+            .entry(path.to_string())
+            .or_default()
+            .push(AttributedLine::new(line, commit, email, covered));
+    }
+
+    pub fn get_file_lines(&self) -> &BTreeMap<String, Vec<AttributedLine>> {
+        &self.file_lines
+    }
+
+    /// Records a commit blamed to the committer, only the first one is kept.
+    /// Lines not committed yet have no commit to look up.
+    pub fn add_user_commit(&mut self, email: &str, commit: &str) {
+        if commit == UNCOMMITTED_COMMIT_ID || self.commits.contains_key(email) {
+            return;
+        }
+        self.commits.insert(email.to_string(), commit.to_string());
+    }
+
+    /// Maps each committer email to one of the commits blamed to them,
+    /// e.g. to look up their GitHub account from the commit author.
+    pub fn get_commits_by_email(&self) -> &BTreeMap<String, String> {
+        &self.commits
+    }
+}
+
+/// Selects and weights the blamed lines that are counted.
+#[derive(Clone, Debug, Default)]
+pub struct AttributionOptions {
+    /// Lines of commits before this time are not counted.
+    pub since: Option<i64>,
+    /// The age in seconds at which a line counts half, for the
+    /// recency-weighted coverage. Not computed if not set.
+    pub half_life: Option<u64>,
+    /// The time the age of the lines is computed at.
+    pub now: i64,
+    /// Whether each coverage line is kept with its committer, e.g. for the
+    /// HTML report or the check run. Not needed for the stats alone.
+    pub collect_lines: bool,
 }
 
 impl CommitterCoverageSummary {
@@ -93,6 +259,7 @@ impl CommitterCoverageSummary {
     >(
         coverage: &A,
         blame: &B,
+        options: &AttributionOptions,
     ) -> Result<CommitterCoverageSummary, String> {
         let file_iter = coverage
             .iter_files()
@@ -105,50 +272,220 @@ impl CommitterCoverageSummary {
         for file in file_iter.into_iter() {
             let path = file.get_path();
             let blame_file = blame.get_file_blame(path);
-            // .map_err(|e| format!("Failed to get blame file: {}", e))?;
-
-            // Handle is blame file error.
-            if let Err(e) = blame_file {
-                // Skipping if the file is not in the git tree but
-                // is in the coverage report. This may be a generated file
-                // or just ignored by git.
-                // TODO: Add input option to ignore files.
-                if e.contains("not exist in the given tree") {
-                    eprintln!("File not found in blame: {}. Skipping...", path);
-                    continue;
-                } else {
-                    return Err(format!("Failed to get blame file: {}", e));
+            CommitterCoverageSummary::add_file_blame(
+                &file,
+                blame_file,
+                options,
+                &mut summary,
+            )?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Same as `from_coverage_file_and_blame`, but blames the files on the
+    /// given number of threads, in batches of the provider's batch size.
+    /// Each thread creates its own blame provider, e.g. its own repository
+    /// handle, since a repository cannot be shared.
+    /// The files are added in the order of the coverage, so the summary
+    /// does not depend on which thread finishes first.
+    /// The options select and weight the lines, see `AttributionOptions`.
+    pub fn from_coverage_file_and_parallel_blame<
+        A: CoverageProvider,
+        B: BlameProvider,
+        F: Fn() -> Result<B, String> + Sync,
+    >(
+        coverage: &A,
+        create_blame: F,
+        threads: usize,
+        options: &AttributionOptions,
+    ) -> Result<CommitterCoverageSummary, String> {
+        let files: Vec<FileCoverage> = coverage
+            .iter_files()
+            .map_err(|e| format!("Failed to get coverage files: {}", e))?
+            .collect();
+
+        let next = AtomicUsize::new(0);
+        let threads = threads.clamp(1, files.len().max(1));
+        let mut blame_files: Vec<(usize, Result<BlameFile, String>)> =
+            thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads)
+                    .map(|_| {
+                        scope.spawn(|| -> Result<_, String> {
+                            let blame = create_blame()?;
+                            let batch_size = blame.get_batch_size().max(1);
+                            let mut blame_files = Vec::new();
+                            loop {
+                                let start =
+                                    next.fetch_add(batch_size, Ordering::Relaxed);
+                                if start >= files.len() {
+                                    break;
+                                }
+                                let end = (start + batch_size).min(files.len());
+                                let paths: Vec<String> = files[start..end]
+                                    .iter()
+                                    .map(|file| file.get_path().to_string())
+                                    .collect();
+                                let results = blame.get_files_blame(&paths);
+                                if results.len() != paths.len() {
+                                    return Err(format!(
+                                        "Expected the blame of {} files, got {}",
+                                        paths.len(),
+                                        results.len()
+                                    ));
+                                }
+                                blame_files.extend((start..end).zip(results));
+                            }
+                            Ok(blame_files)
+                        })
+                    })
+                    .collect();
+
+                let mut blame_files = Vec::with_capacity(files.len());
+                for worker in workers {
+                    let worker_files = worker
+                        .join()
+                        .map_err(|_| "Blame thread panicked".to_string())??;
+                    blame_files.extend(worker_files);
                 }
-            }
-            
-            let blame_file = blame_file.unwrap();
-            CommitterCoverageSummary::calculate_by_lines(
-                file.get_lines(),
-                blame_file.get_lines(),
+                Ok::<_, String>(blame_files)
+            })?;
+        blame_files.sort_by_key(|(i, _)| *i);
+
+        let mut summary: CommitterCoverageSummary =
+            CommitterCoverageSummary::default();
+        for (i, blame_file) in blame_files {
+            CommitterCoverageSummary::add_file_blame(
+                &files[i],
+                blame_file,
+                options,
                 &mut summary,
-            )
+            )?;
         }
 
         Ok(summary)
     }
 
+    fn add_file_blame(
+        file: &FileCoverage,
+        blame_file: Result<BlameFile, String>,
+        options: &AttributionOptions,
+        summary: &mut CommitterCoverageSummary,
+    ) -> Result<(), String> {
+        let path = file.get_path();
+        // Handle is blame file error.
+        let blame_file = match blame_file {
+            Ok(blame_file) => blame_file,
+            // The file is not in the git tree but is in the coverage
+            // report. This may be a generated file or just ignored by git,
+            // so its lines are counted as unattributed.
+            // TODO: Add input option to ignore files.
+            Err(e) if e.contains("not exist in the given tree") => {
+                eprintln!("File not found in blame: {}. Counting as unattributed...", path);
+                for covered in file.get_lines().values() {
+                    summary.incr_unattributed_line(path, *covered);
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(format!("Failed to get blame file: {}", e)),
+        };
+
+        CommitterCoverageSummary::calculate_by_lines(
+            path,
+            file.get_lines(),
+            blame_file.get_lines(),
+            options,
+            summary,
+        );
+        Ok(())
+    }
+
+    /// Lines of commits before `since` are skipped, lines of commits
+    /// without a known time are counted, and weighted as new lines.
+    /// Lines without a blame, e.g. from stale coverage, are unattributed.
     fn calculate_by_lines(
+        path: &str,
         coverage_lines: &BTreeMap<u32, bool>,
         blame_lines: &BTreeMap<u32, BlameLine>,
+        options: &AttributionOptions,
         summary: &mut CommitterCoverageSummary,
     ) {
         for (line_num, covered) in coverage_lines.iter() {
-            let blame_line = blame_lines.get(line_num);
-            if blame_line.is_none() {
-                continue;
+            let blame_line = match blame_lines.get(line_num) {
+                Some(blame_line) => blame_line,
+                None => {
+                    summary.incr_unattributed_line(path, *covered);
+                    continue;
+                }
+            };
+            if let (Some(since), Some(time)) = (options.since, blame_line.get_time()) {
+                if time < since {
+                    continue;
+                }
             }
-            let blame_line = blame_line.unwrap();
             let email = &blame_line.must_get_email();
             let name = blame_line.get_name();
             summary.create_user_stat_if_not_exists(email, name.clone());
-            summary.incr_user_line_cover(email, *covered);
+            if let Some(half_life) = options.half_life {
+                let age = blame_line.get_time().map_or(0, |time| options.now - time);
+                let weight = recency_weight(age, half_life);
+                summary.incr_user_line_weight(email, weight, *covered);
+            }
+            summary.incr_user_line_cover(email, path, *covered);
+            if options.collect_lines {
+                summary.add_attributed_line(
+                    path,
+                    *line_num,
+                    blame_line.get_commit(),
+                    email,
+                    *covered,
+                );
+            } else {
+                summary.add_user_commit(email, blame_line.get_commit());
+            }
+        }
+    }
+}
+
+/// Represents a single coverage line and the committer it is blamed to.
+#[derive(Clone)]
+pub struct AttributedLine {
+    line: u32,
+    commit: String,
+    email: String,
+    covered: bool,
+}
+
+impl AttributedLine {
+    pub fn new(
+        line: u32,
+        commit: &str,
+        email: &str,
+        covered: bool,
+    ) -> AttributedLine {
+        AttributedLine {
+            line,
+            commit: commit.to_string(),
+            email: email.to_string(),
+            covered,
         }
     }
+
+    pub fn get_line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn get_commit(&self) -> &str {
+        &self.commit
+    }
+
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn is_covered(&self) -> bool {
+        self.covered
+    }
 }
 
 /// Represents the coverage statistics for a single committer.
@@ -160,6 +497,10 @@ pub struct CommitterCoverageUserStat {
     lines: u32,
     covered: u32,
     percent_covered: f32,
+    /// Maps file path to the coverage of the lines blamed to this user.
+    files: BTreeMap<String, CommitterCoverageFileStat>,
+    /// The coverage weighted by the age of the lines, if enabled.
+    recency_weighted: Option<WeightedCoverage>,
 }
 
 impl CommitterCoverageUserStat {
@@ -169,16 +510,15 @@ impl CommitterCoverageUserStat {
         lines: u32,
         covered: u32,
     ) -> CommitterCoverageUserStat {
-        let percent_covered = match lines {
-            0 => 0.0,
-            _ => covered as f32 / lines as f32 * 100.0,
-        };
+        let percent_covered = percent(covered, lines);
         CommitterCoverageUserStat {
             email: email.to_string(),
             name,
             lines,
             covered,
             percent_covered,
+            files: BTreeMap::new(),
+            recency_weighted: None,
         }
     }
 
@@ -201,6 +541,381 @@ impl CommitterCoverageUserStat {
     pub fn get_percent_covered(&self) -> f32 {
         self.percent_covered
     }
+
+    pub fn get_files(&self) -> &BTreeMap<String, CommitterCoverageFileStat> {
+        &self.files
+    }
+
+    /// The coverage weighted by the age of the lines, shown next to
+    /// the raw percentage. None if the weighting is not enabled.
+    pub fn get_recency_weighted(&self) -> &Option<WeightedCoverage> {
+        &self.recency_weighted
+    }
+
+    pub fn set_recency_weighted(&mut self, recency_weighted: Option<WeightedCoverage>) {
+        self.recency_weighted = recency_weighted;
+    }
+
+    /// Adds the stat of a single file, e.g. when loading from a report.
+    /// This does not change the totals of the user.
+    pub fn add_file_stat(&mut self, file_stat: CommitterCoverageFileStat) {
+        self.files.insert(file_stat.get_path().to_string(), file_stat);
+    }
+
+    /// Rolls the file statistics up to directories.
+    /// Only the first `depth` directory components of each path are kept,
+    /// e.g. with depth 1 `src/coverage/lcov.rs` is counted under `src`.
+    /// Files at the root of the repository are counted under `.`.
+    pub fn get_directory_stats(
+        &self,
+        depth: usize,
+    ) -> BTreeMap<String, CommitterCoverageFileStat> {
+        let mut dirs: BTreeMap<String, CommitterCoverageFileStat> =
+            BTreeMap::new();
+        for file in self.files.values() {
+            let dir = directory_of_path(file.get_path(), depth);
+            let dir_stat = dirs
+                .entry(dir.clone())
+                .or_insert_with(|| CommitterCoverageFileStat::new(&dir, 0, 0));
+            dir_stat.lines += file.lines;
+            dir_stat.covered += file.covered;
+        }
+        dirs
+    }
+
+    /// Returns at most `limit` files that still have uncovered lines,
+    /// sorted from the lowest coverage percentage.
+    pub fn get_worst_covered_files(
+        &self,
+        limit: usize,
+    ) -> Vec<&CommitterCoverageFileStat> {
+        let mut files: Vec<&CommitterCoverageFileStat> = self
+            .files
+            .values()
+            .filter(|file| file.get_uncovered() > 0)
+            .collect();
+        files.sort_by(|a, b| {
+            a.get_percent_covered()
+                .partial_cmp(&b.get_percent_covered())
+                .unwrap()
+                .then(b.get_uncovered().cmp(&a.get_uncovered()))
+                .then(a.get_path().cmp(b.get_path()))
+        });
+        files.truncate(limit);
+        files
+    }
+}
+
+/// Represents the coverage statistics of a committer in a single file
+/// or, when rolled up, in a single directory.
+#[derive(Clone, Default)]
+pub struct CommitterCoverageFileStat {
+    path: String,
+    lines: u32,
+    covered: u32,
+}
+
+impl CommitterCoverageFileStat {
+    pub fn new(path: &str, lines: u32, covered: u32) -> CommitterCoverageFileStat {
+        CommitterCoverageFileStat {
+            path: path.to_string(),
+            lines,
+            covered,
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_lines(&self) -> u32 {
+        self.lines
+    }
+
+    pub fn get_covered(&self) -> u32 {
+        self.covered
+    }
+
+    pub fn get_uncovered(&self) -> u32 {
+        self.lines - self.covered
+    }
+
+    pub fn get_percent_covered(&self) -> f32 {
+        percent(self.covered, self.lines)
+    }
+}
+
+/// Returns the weight of a line of the given age in seconds, halved every
+/// half-life, e.g. 1 for a new line and 0.25 for a line two half-lives old.
+pub fn recency_weight(age: i64, half_life: u64) -> f64 {
+    if half_life == 0 {
+        return 1.0;
+    }
+    0.5_f64.powf(age.max(0) as f64 / half_life as f64)
+}
+
+/// The sum of the weights of the lines and of the covered lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WeightedCoverage {
+    lines: f64,
+    covered: f64,
+}
+
+impl WeightedCoverage {
+    pub fn new(lines: f64, covered: f64) -> WeightedCoverage {
+        WeightedCoverage { lines, covered }
+    }
+
+    pub fn add(&mut self, weight: f64, covered: bool) {
+        self.lines += weight;
+        if covered {
+            self.covered += weight;
+        }
+    }
+
+    pub fn get_lines(&self) -> f64 {
+        self.lines
+    }
+
+    pub fn get_covered(&self) -> f64 {
+        self.covered
+    }
+
+    pub fn get_percent_covered(&self) -> f32 {
+        if self.lines <= 0.0 {
+            return 0.0;
+        }
+        (self.covered / self.lines * 100.0) as f32
+    }
+}
+
+/// Returns the percentage of covered lines, or 0 if there are no lines.
+fn percent(covered: u32, lines: u32) -> f32 {
+    match lines {
+        0 => 0.0,
+        _ => covered as f32 / lines as f32 * 100.0,
+    }
+}
+
+/// Returns the directory of the path truncated to `depth` components.
+fn directory_of_path(path: &str, depth: usize) -> String {
+    let path = path.trim_start_matches("./");
+    let components: Vec<&str> = path.split('/').collect();
+    // the last component is the file name.
+    let dirs = &components[..components.len() - 1];
+    let dirs = &dirs[..dirs.len().min(depth)];
+    if dirs.is_empty() {
+        ".".to_string()
+    } else {
+        dirs.join("/")
+    }
+}
+
+/// Decides whether a committer passes the coverage threshold.
+///
+/// Committers with fewer lines than `min_lines` get no verdict at all,
+/// so a couple of uncovered lines do not mark someone as failing.
+///
+/// The default threshold applies unless a rule overrides it:
+/// - A committer rule, matched by email or name, applies to all lines of that
+///   committer, e.g. a lower ramp-up threshold for new hires.
+/// - Otherwise, each file is checked against the first path rule whose glob
+///   matches it, e.g. `src/core/**` requires 90%. The lines of the files
+///   matching the same rule are counted together, and the remaining files
+///   are counted together against the default threshold.
+#[derive(Clone)]
+pub struct ThresholdRules {
+    default: f32,
+    min_lines: u32,
+    paths: Vec<(Pattern, f32)>,
+    committers: Vec<(String, f32)>,
+}
+
+impl ThresholdRules {
+    pub fn new(default: f32) -> ThresholdRules {
+        ThresholdRules {
+            default,
+            min_lines: 0,
+            paths: Vec::new(),
+            committers: Vec::new(),
+        }
+    }
+
+    /// Adds a rule for the files matching the glob, e.g. `src/core/**`.
+    /// Rules are matched in the order they are added.
+    pub fn add_path_threshold(
+        &mut self,
+        glob: &str,
+        threshold: f32,
+    ) -> Result<(), String> {
+        let pattern = Pattern::new(glob)
+            .map_err(|err| format!("Invalid glob {}: {}", glob, err))?;
+        self.paths.push((pattern, threshold));
+        Ok(())
+    }
+
+    /// Adds a rule for the committer with the given email or name.
+    pub fn add_committer_threshold(&mut self, identity: &str, threshold: f32) {
+        self.committers.push((identity.to_string(), threshold));
+    }
+
+    pub fn get_default(&self) -> f32 {
+        self.default
+    }
+
+    /// Sets the minimum number of lines a committer needs to get a verdict.
+    pub fn set_min_lines(&mut self, min_lines: u32) {
+        self.min_lines = min_lines;
+    }
+
+    pub fn get_min_lines(&self) -> u32 {
+        self.min_lines
+    }
+
+    /// Returns whether the committer has too few lines to get a verdict.
+    pub fn is_below_min_lines(&self, user_stat: &CommitterCoverageUserStat) -> bool {
+        user_stat.get_lines() < self.min_lines
+    }
+
+    /// Returns the threshold of the committer rule, if any matches the user.
+    pub fn get_committer_threshold(
+        &self,
+        user_stat: &CommitterCoverageUserStat,
+    ) -> Option<f32> {
+        self.committers
+            .iter()
+            .find(|(identity, _)| {
+                identity.eq_ignore_ascii_case(user_stat.get_email())
+                    || Some(identity) == user_stat.get_name().as_ref()
+            })
+            .map(|(_, threshold)| *threshold)
+    }
+
+    /// Returns the glob and threshold of the first path rule matching the path.
+    pub fn get_path_threshold(&self, path: &str) -> Option<(&str, f32)> {
+        self.find_path_rule(path).map(|index| {
+            let (pattern, threshold) = &self.paths[index];
+            (pattern.as_str(), *threshold)
+        })
+    }
+
+    fn find_path_rule(&self, path: &str) -> Option<usize> {
+        let path = path.trim_start_matches("./");
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.paths
+            .iter()
+            .position(|(pattern, _)| pattern.matches_with(path, options))
+    }
+
+    pub fn evaluate(&self, user_stat: &CommitterCoverageUserStat) -> ThresholdDecision {
+        let mut decision = ThresholdDecision::default();
+        if self.is_below_min_lines(user_stat) {
+            decision.skipped = true;
+            return decision;
+        }
+
+        let committer_threshold = self.get_committer_threshold(user_stat);
+        if committer_threshold.is_some()
+            || self.paths.is_empty()
+            || user_stat.get_files().is_empty()
+        {
+            let threshold = committer_threshold.unwrap_or(self.default);
+            decision.check(
+                "*",
+                user_stat.get_lines(),
+                user_stat.get_covered(),
+                threshold,
+            );
+            return decision;
+        }
+
+        // group the lines by the index of the matching rule,
+        // the files without a matching rule come last.
+        let mut groups: BTreeMap<usize, (u32, u32)> = BTreeMap::new();
+        for file in user_stat.get_files().values() {
+            let index = self
+                .find_path_rule(file.get_path())
+                .unwrap_or(self.paths.len());
+            let group = groups.entry(index).or_default();
+            group.0 += file.get_lines();
+            group.1 += file.get_covered();
+        }
+
+        for (index, (lines, covered)) in groups {
+            let (scope, threshold) = match self.paths.get(index) {
+                Some((pattern, threshold)) => (pattern.as_str(), *threshold),
+                None => ("*", self.default),
+            };
+            decision.check(scope, lines, covered, threshold);
+        }
+        decision
+    }
+}
+
+/// Represents the result of checking a committer against the threshold rules.
+#[derive(Clone, Default)]
+pub struct ThresholdDecision {
+    failures: Vec<ThresholdFailure>,
+    /// Whether the committer has too few lines to get a verdict.
+    skipped: bool,
+}
+
+/// Represents a group of lines of a committer that is below its threshold.
+/// The scope is the glob of the path rule, or `*` for the other lines.
+#[derive(Clone)]
+pub struct ThresholdFailure {
+    pub scope: String,
+    pub percent_covered: f32,
+    pub threshold: f32,
+}
+
+impl ThresholdDecision {
+    fn check(&mut self, scope: &str, lines: u32, covered: u32, threshold: f32) {
+        let percent_covered = percent(covered, lines);
+        if percent_covered < threshold {
+            self.failures.push(ThresholdFailure {
+                scope: scope.to_string(),
+                percent_covered,
+                threshold,
+            });
+        }
+    }
+
+    /// Returns whether the committer passed, which is also true if skipped.
+    pub fn is_passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    pub fn get_failures(&self) -> &Vec<ThresholdFailure> {
+        &self.failures
+    }
+}
+
+/// Parses rules in the format `key=threshold`, separated by comma,
+/// e.g. `src/core/**=90,cli/**=60`.
+pub fn parse_threshold_rules(rules: &str) -> Result<Vec<(String, f32)>, String> {
+    rules
+        .split(',')
+        .map(|rule| rule.trim())
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let (key, threshold) = rule
+                .rsplit_once('=')
+                .ok_or(format!("Invalid rule {}, expected key=threshold", rule))?;
+            let threshold = threshold
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid threshold in rule {}", rule))?;
+            Ok((key.trim().to_string(), threshold))
+        })
+        .collect()
 }
 
 pub fn load_coverage_files() {
@@ -211,6 +926,11 @@ pub fn load_coverage_files() {
 mod tests {
 
     use super::*;
+    use crate::{
+        coverage::MockCoverageProvider,
+        git::{BlameAuthor, BlameCommit, MockBlameProvider},
+    };
+    use std::sync::Arc;
 
     #[test]
     fn test_committer_coverage_user_stat_percent_covered() {
@@ -235,6 +955,20 @@ mod tests {
 
         assert_eq!(10, user_stat.get_lines());
         assert_eq!(5, user_stat.get_covered());
+        assert_eq!(50.0, user_stat.get_percent_covered());
+    }
+
+    #[test]
+    fn test_committer_coverage_summary_from_user_stats() {
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user1@example.com", None, 10, 5),
+            CommitterCoverageUserStat::new("user2@example.com", None, 30, 25),
+        ]);
+
+        assert_eq!(40, summary.get_lines());
+        assert_eq!(30, summary.get_covered());
+        assert_eq!(75.0, summary.get_percent_covered());
+        assert_eq!(2, summary.get_user_stats().len());
     }
 
     #[test]
@@ -245,7 +979,7 @@ mod tests {
                 .into_iter()
                 .collect();
 
-        let blame_lines: BTreeMap<u32, BlameLine> = vec![1, 2, 3, 4, 5].iter()
+        let blame_lines: BTreeMap<u32, BlameLine> = [1, 2, 3, 4, 5].iter()
         .map(|i| {
             (*i, BlameLine::new(
                 *i,
@@ -253,17 +987,351 @@ mod tests {
                 Some(format!("user{}", i)),
                 Some(format!("user{}", i)),
             ))
-        }).into_iter()
+        })
         .collect();
 
+        let options = AttributionOptions {
+            collect_lines: true,
+            ..Default::default()
+        };
         CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
             &coverage_lines,
             &blame_lines,
+            &options,
             &mut summary,
         );
 
         assert_eq!(5, summary.get_lines());
         assert_eq!(3, summary.get_covered());
         assert_eq!(5, summary.get_user_stats().len());
+
+        let user_stat = summary.get_user_stats().get("user2").unwrap();
+        let file_stat = user_stat.get_files().get("src/main.rs").unwrap();
+        assert_eq!(1, file_stat.get_lines());
+        assert_eq!(0, file_stat.get_covered());
+
+        let lines = summary.get_file_lines().get("src/main.rs").unwrap();
+        assert_eq!(5, lines.len());
+        assert_eq!(2, lines[1].get_line());
+        assert_eq!("commit2", lines[1].get_commit());
+        let commits = summary.get_commits_by_email();
+        assert_eq!(Some(&"commit2".to_string()), commits.get("user2"));
+        assert_eq!("user2", lines[1].get_email());
+        assert!(!lines[1].is_covered());
+
+        // without collecting the lines, the commits are still known.
+        let mut summary = CommitterCoverageSummary::default();
+        CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
+            &coverage_lines,
+            &blame_lines,
+            &AttributionOptions::default(),
+            &mut summary,
+        );
+        assert_eq!(5, summary.get_lines());
+        assert!(summary.get_file_lines().is_empty());
+        let commits = summary.get_commits_by_email();
+        assert_eq!(Some(&"commit2".to_string()), commits.get("user2"));
+    }
+
+    #[test]
+    fn test_calculate_by_lines_since() {
+        let mut summary = CommitterCoverageSummary::default();
+        let coverage_lines =
+            vec![(1, true), (2, false), (3, true)].into_iter().collect();
+
+        let mut blame_lines: BTreeMap<u32, BlameLine> = BTreeMap::new();
+        for (line, time) in [(1, Some(1000)), (2, Some(3000)), (3, None)] {
+            let author = Arc::new(BlameAuthor::new(Some("user".to_string()), None));
+            let mut commit = BlameCommit::new(&format!("commit{}", line), author);
+            commit.set_time(time);
+            blame_lines.insert(line, BlameLine::new_from_commit(line, &Arc::new(commit)));
+        }
+
+        CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
+            &coverage_lines,
+            &blame_lines,
+            &AttributionOptions {
+                since: Some(2000),
+                collect_lines: true,
+                ..Default::default()
+            },
+            &mut summary,
+        );
+
+        // the line of the older commit is skipped, the one without a time is not.
+        assert_eq!(2, summary.get_lines());
+        assert_eq!(1, summary.get_covered());
+        let lines = summary.get_file_lines().get("src/main.rs").unwrap();
+        assert_eq!(vec![2, 3], lines.iter().map(|line| line.get_line()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_calculate_by_lines_unattributed() {
+        let mut summary = CommitterCoverageSummary::default();
+        let coverage_lines =
+            vec![(1, true), (2, false), (3, true)].into_iter().collect();
+        let blame_lines: BTreeMap<u32, BlameLine> =
+            [(1, BlameLine::new(1, "commit1", Some("user".to_string()), None))]
+                .into_iter()
+                .collect();
+
+        CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
+            &coverage_lines,
+            &blame_lines,
+            &AttributionOptions::default(),
+            &mut summary,
+        );
+
+        // the lines past the blame, e.g. from stale coverage, are not in the totals.
+        assert_eq!(1, summary.get_lines());
+        let unattributed = summary.get_unattributed();
+        assert_eq!(2, unattributed.get_lines());
+        assert_eq!(1, unattributed.get_covered());
+
+        // a file that is not in the git tree is unattributed as a whole.
+        let mut file = FileCoverage::new("src/generated.rs");
+        file.add_line(1, true);
+        file.add_line(2, false);
+        CommitterCoverageSummary::add_file_blame(
+            &file,
+            Err("the path 'src/generated.rs' does not exist in the given tree".to_string()),
+            &AttributionOptions::default(),
+            &mut summary,
+        )
+        .unwrap();
+        assert_eq!(1, summary.get_lines());
+        assert_eq!(4, summary.get_unattributed().get_lines());
+        let file_stat = summary.get_unattributed_files().get("src/generated.rs").unwrap();
+        assert_eq!(2, file_stat.get_lines());
+        assert_eq!(1, file_stat.get_covered());
+    }
+
+    #[test]
+    fn test_calculate_by_lines_recency_weighted() {
+        let mut summary = CommitterCoverageSummary::default();
+        let coverage_lines =
+            vec![(1, true), (2, false), (3, true)].into_iter().collect();
+
+        let mut blame_lines: BTreeMap<u32, BlameLine> = BTreeMap::new();
+        for (line, time) in [(1, Some(1000)), (2, Some(2000)), (3, None)] {
+            let author = Arc::new(BlameAuthor::new(Some("user".to_string()), None));
+            let mut commit = BlameCommit::new(&format!("commit{}", line), author);
+            commit.set_time(time);
+            blame_lines.insert(line, BlameLine::new_from_commit(line, &Arc::new(commit)));
+        }
+
+        CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
+            &coverage_lines,
+            &blame_lines,
+            &AttributionOptions {
+                half_life: Some(1000),
+                now: 2000,
+                ..Default::default()
+            },
+            &mut summary,
+        );
+
+        // the line one half-life old counts half, the one without a time as new.
+        assert_eq!(3, summary.get_lines());
+        let weighted = WeightedCoverage::new(2.5, 1.5);
+        assert_eq!(&Some(weighted), summary.get_recency_weighted());
+        let user = summary.get_user_stats().get("user").unwrap();
+        assert_eq!(&Some(weighted), user.get_recency_weighted());
+        assert_eq!(60.0, weighted.get_percent_covered());
+
+        // without a half-life nothing is weighted.
+        let mut summary = CommitterCoverageSummary::default();
+        CommitterCoverageSummary::calculate_by_lines(
+            "src/main.rs",
+            &coverage_lines,
+            &blame_lines,
+            &AttributionOptions::default(),
+            &mut summary,
+        );
+        assert_eq!(&None, summary.get_recency_weighted());
+    }
+
+    #[test]
+    fn test_recency_weight() {
+        assert_eq!(1.0, recency_weight(0, 100));
+        assert_eq!(0.5, recency_weight(100, 100));
+        assert_eq!(0.25, recency_weight(200, 100));
+        // a commit in the future is as new as a commit now.
+        assert_eq!(1.0, recency_weight(-100, 100));
+        assert_eq!(1.0, recency_weight(100, 0));
+    }
+
+    fn create_mock_coverage(files: usize) -> MockCoverageProvider {
+        let mut coverage = MockCoverageProvider::new();
+        coverage.expect_iter_files().returning(move || {
+            let files = (0..files).map(|i| {
+                let mut file = FileCoverage::new(&format!("src/file{}.rs", i));
+                file.add_line(1, true);
+                file.add_line(2, i % 2 == 0);
+                file
+            });
+            Ok(Box::new(files))
+        });
+        coverage
+    }
+
+    fn create_mock_file_blame(path: &str) -> Result<BlameFile, String> {
+        if path == "src/file3.rs" {
+            return Err("the path does not exist in the given tree".to_string());
+        }
+        let mut blame_file = BlameFile::new_from_path(path);
+        for line in 1..=2 {
+            let email = format!("user{}@example.com", path.len() + line as usize);
+            blame_file.add_line(line, "abc", Some(email), None);
+        }
+        Ok(blame_file)
+    }
+
+    fn create_mock_blame() -> Result<MockBlameProvider, String> {
+        let mut blame = MockBlameProvider::new();
+        blame
+            .expect_get_file_blame()
+            .returning(create_mock_file_blame);
+        blame.expect_get_batch_size().return_const(3usize);
+        blame.expect_get_files_blame().returning(|paths| {
+            assert!(paths.len() <= 3);
+            paths.iter().map(|path| create_mock_file_blame(path)).collect()
+        });
+        Ok(blame)
+    }
+
+    #[test]
+    fn test_from_coverage_file_and_parallel_blame() {
+        let coverage = create_mock_coverage(25);
+        let options = AttributionOptions {
+            collect_lines: true,
+            ..Default::default()
+        };
+        let expected = CommitterCoverageSummary::from_coverage_file_and_blame(
+            &coverage,
+            &create_mock_blame().unwrap(),
+            &options,
+        )
+        .unwrap();
+
+        for threads in [1, 4, 100] {
+            let summary =
+                CommitterCoverageSummary::from_coverage_file_and_parallel_blame(
+                    &coverage,
+                    create_mock_blame,
+                    threads,
+                    &options,
+                )
+                .unwrap();
+            assert_eq!(expected.get_lines(), summary.get_lines());
+            assert_eq!(expected.get_covered(), summary.get_covered());
+            // the skipped file is not counted.
+            assert_eq!(48, summary.get_lines());
+            let paths: Vec<&String> = summary.get_file_lines().keys().collect();
+            let expected_paths: Vec<&String> =
+                expected.get_file_lines().keys().collect();
+            assert_eq!(expected_paths, paths);
+            for (email, stat) in expected.get_user_stats() {
+                let user_stat = summary.get_user_stats().get(email).unwrap();
+                assert_eq!(stat.get_lines(), user_stat.get_lines());
+                assert_eq!(stat.get_covered(), user_stat.get_covered());
+            }
+        }
+
+        let result = CommitterCoverageSummary::from_coverage_file_and_parallel_blame(
+            &coverage,
+            || Err::<MockBlameProvider, String>("Failed to open".to_string()),
+            2,
+            &AttributionOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_threshold_rules_evaluate() {
+        let mut summary = CommitterCoverageSummary::default();
+        for email in ["user@example.com", "newhire@example.com"] {
+            summary.create_user_stat_if_not_exists(email, None);
+            // core: 9 / 10 covered, cli: 6 / 10 covered.
+            for i in 0..10 {
+                summary.incr_user_line_cover(email, "src/core/lib.rs", i < 9);
+                summary.incr_user_line_cover(email, "src/cli/main.rs", i < 6);
+            }
+        }
+        let user = summary.get_user_stats().get("user@example.com").unwrap();
+        let newhire = summary.get_user_stats().get("newhire@example.com").unwrap();
+
+        // 75% overall is below the default.
+        let rules = ThresholdRules::new(80.0);
+        assert!(!rules.evaluate(user).is_passed());
+
+        let mut rules = ThresholdRules::new(80.0);
+        rules.add_path_threshold("src/core/**", 90.0).unwrap();
+        rules.add_path_threshold("src/cli/*", 60.0).unwrap();
+        assert!(rules.evaluate(user).is_passed());
+
+        let mut rules = ThresholdRules::new(80.0);
+        rules.add_path_threshold("src/core/**", 95.0).unwrap();
+        let decision = rules.evaluate(user);
+        let scopes: Vec<&str> =
+            decision.get_failures().iter().map(|f| f.scope.as_str()).collect();
+        assert_eq!(vec!["src/core/**", "*"], scopes);
+
+        rules.add_committer_threshold("newhire@example.com", 70.0);
+        assert!(rules.evaluate(newhire).is_passed());
+        assert!(!rules.evaluate(user).is_passed());
+
+        rules.set_min_lines(21);
+        let decision = rules.evaluate(user);
+        assert!(decision.is_skipped());
+        assert!(decision.is_passed());
+    }
+
+    #[test]
+    fn test_parse_threshold_rules() {
+        let rules = parse_threshold_rules("src/core/**=90, cli/**=60").unwrap();
+        assert_eq!(
+            vec![("src/core/**".to_string(), 90.0), ("cli/**".to_string(), 60.0)],
+            rules
+        );
+        assert!(parse_threshold_rules("").unwrap().is_empty());
+        assert!(parse_threshold_rules("src/**").is_err());
+        assert!(parse_threshold_rules("src/**=high").is_err());
+    }
+
+    #[test]
+    fn test_committer_coverage_user_stat_directory_stats_and_worst_files() {
+        let mut summary = CommitterCoverageSummary::default();
+        let email = "user@example.com";
+        summary.create_user_stat_if_not_exists(email, None);
+        summary.incr_user_line_cover(email, "README.md", true);
+        summary.incr_user_line_cover(email, "src/lib.rs", true);
+        summary.incr_user_line_cover(email, "src/coverage/lcov.rs", false);
+        summary.incr_user_line_cover(email, "src/coverage/cobertura.rs", true);
+        summary.incr_user_line_cover(email, "src/coverage/cobertura.rs", false);
+
+        let user_stat = summary.get_user_stats().get(email).unwrap();
+
+        let dirs = user_stat.get_directory_stats(1);
+        assert_eq!(2, dirs.len());
+        assert_eq!(1, dirs.get(".").unwrap().get_lines());
+        assert_eq!(4, dirs.get("src").unwrap().get_lines());
+        assert_eq!(2, dirs.get("src").unwrap().get_covered());
+
+        let dirs = user_stat.get_directory_stats(2);
+        assert_eq!(3, dirs.get("src/coverage").unwrap().get_lines());
+
+        let worst = user_stat.get_worst_covered_files(5);
+        let paths: Vec<&str> = worst.iter().map(|f| f.get_path()).collect();
+        assert_eq!(
+            vec!["src/coverage/lcov.rs", "src/coverage/cobertura.rs"],
+            paths
+        );
+        assert_eq!(1, user_stat.get_worst_covered_files(1).len());
     }
 }
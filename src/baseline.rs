@@ -0,0 +1,115 @@
+//! This module compares the committer coverage against a baseline,
+//! usually the report of an earlier run on the target branch.
+use crate::analysis::CommitterCoverageSummary;
+use std::collections::HashMap;
+
+/// Represents the coverage deltas between the baseline and the current summary.
+/// The deltas are in percentage points, positive means the coverage went up.
+#[derive(Clone, Default)]
+pub struct BaselineComparison {
+    total_delta: f32,
+    /// Maps the email to the delta. Committers that are not in the
+    /// baseline have no entry.
+    user_deltas: HashMap<String, f32>,
+}
+
+impl BaselineComparison {
+    pub fn new(
+        baseline: &CommitterCoverageSummary,
+        current: &CommitterCoverageSummary,
+    ) -> BaselineComparison {
+        let total_delta =
+            current.get_percent_covered() - baseline.get_percent_covered();
+
+        let baseline_stats = baseline.get_user_stats();
+        let user_deltas = current
+            .get_user_stats()
+            .iter()
+            .filter_map(|(email, stat)| {
+                baseline_stats.get(email).map(|baseline_stat| {
+                    let delta = stat.get_percent_covered()
+                        - baseline_stat.get_percent_covered();
+                    (email.to_string(), delta)
+                })
+            })
+            .collect();
+
+        BaselineComparison {
+            total_delta,
+            user_deltas,
+        }
+    }
+
+    pub fn get_total_delta(&self) -> f32 {
+        self.total_delta
+    }
+
+    /// Returns the delta of the user, or None if the user is not in the baseline.
+    pub fn get_user_delta(&self, email: &str) -> Option<f32> {
+        self.user_deltas.get(email).copied()
+    }
+
+    /// Returns the committers whose coverage dropped by more than `max_drop`
+    /// percentage points, sorted by email.
+    pub fn get_regressions(&self, max_drop: f32) -> Vec<(String, f32)> {
+        let mut regressions: Vec<(String, f32)> = self
+            .user_deltas
+            .iter()
+            .filter(|(_, delta)| -**delta > max_drop)
+            .map(|(email, delta)| (email.to_string(), *delta))
+            .collect();
+        regressions.sort_by(|a, b| a.0.cmp(&b.0));
+        regressions
+    }
+}
+
+/// Formats the delta with an arrow, e.g. `▲ 1.50` or `▼ 2.00`.
+pub fn format_delta(delta: f32) -> String {
+    if delta >= 0.005 {
+        format!("▲ {:.2}", delta)
+    } else if delta <= -0.005 {
+        format!("▼ {:.2}", -delta)
+    } else {
+        "0.00".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::CommitterCoverageUserStat;
+
+    #[test]
+    fn test_baseline_comparison() {
+        let baseline = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user1@example.com", None, 10, 5),
+            CommitterCoverageUserStat::new("user2@example.com", None, 10, 9),
+        ]);
+        let current = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user1@example.com", None, 10, 8),
+            CommitterCoverageUserStat::new("user2@example.com", None, 10, 6),
+            CommitterCoverageUserStat::new("user3@example.com", None, 10, 10),
+        ]);
+
+        let comparison = BaselineComparison::new(&baseline, &current);
+
+        assert!((comparison.get_total_delta() - 10.0).abs() < 0.01);
+        let delta1 = comparison.get_user_delta("user1@example.com").unwrap();
+        assert!((delta1 - 30.0).abs() < 0.01);
+        let delta2 = comparison.get_user_delta("user2@example.com").unwrap();
+        assert!((delta2 + 30.0).abs() < 0.01);
+        assert_eq!(None, comparison.get_user_delta("user3@example.com"));
+
+        let regressions = comparison.get_regressions(5.0);
+        assert_eq!(1, regressions.len());
+        assert_eq!("user2@example.com", regressions[0].0);
+        assert!(comparison.get_regressions(31.0).is_empty());
+    }
+
+    #[test]
+    fn test_format_delta() {
+        assert_eq!("▲ 1.50", format_delta(1.5));
+        assert_eq!("▼ 2.00", format_delta(-2.0));
+        assert_eq!("0.00", format_delta(0.001));
+    }
+}
@@ -0,0 +1,200 @@
+//! Config file
+//! This module loads the checked-in config file, e.g. `.committer-coverage.toml`.
+//! The values are flattened into strings so they are parsed and validated
+//! the same way as the action inputs.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// The file names looked up in the workspace, in order.
+pub const DEFAULT_FILE_NAMES: &[&str] = &[
+    ".committer-coverage.toml",
+    ".committer-coverage.yml",
+    ".committer-coverage.yaml",
+];
+
+/// Returns the first default config file that exists in the directory.
+pub fn find_in_dir(dir: &str) -> Option<PathBuf> {
+    DEFAULT_FILE_NAMES
+        .iter()
+        .map(|name| Path::new(dir).join(name))
+        .find(|path| path.is_file())
+}
+
+/// Loads the config file and returns the values by key.
+/// The format is chosen by the extension, `.toml` or `.yml`/`.yaml`.
+/// Keys that are not in `known_keys` are rejected.
+pub fn load(
+    path: &Path,
+    known_keys: &[&str],
+) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        format!("Failed to read config file {}: {}", path.display(), err)
+    })?;
+
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let values = match extension {
+        Some("toml") => parse_toml(&content),
+        Some("yml") | Some("yaml") => parse_yaml(&content),
+        _ => Err("unknown format, expected .toml, .yml or .yaml".to_string()),
+    }
+    .map_err(|err| format!("Invalid config file {}: {}", path.display(), err))?;
+
+    for key in values.keys() {
+        if !known_keys.contains(&key.as_str()) {
+            return Err(format!(
+                "Invalid config file {}: unknown key {}",
+                path.display(),
+                key
+            ));
+        }
+    }
+
+    Ok(values)
+}
+
+fn parse_toml(content: &str) -> Result<HashMap<String, String>, String> {
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|err| err.message().to_string())?;
+
+    table
+        .iter()
+        .map(|(key, value)| {
+            let value = flatten_toml(value)
+                .map_err(|err| format!("{} {}", key, err))?;
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Flattens the value into the same format as the action inputs.
+/// Arrays become comma separated, tables become comma separated `key=value`.
+fn flatten_toml(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.to_string()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(flatten_toml)
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(values.join(","))
+        }
+        toml::Value::Table(table) => {
+            let values = table
+                .iter()
+                .map(|(k, v)| Ok(format!("{}={}", k, flatten_toml(v)?)))
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(values.join(","))
+        }
+        toml::Value::Datetime(_) => Err("must not be a date".to_string()),
+    }
+}
+
+fn parse_yaml(content: &str) -> Result<HashMap<String, String>, String> {
+    let docs = YamlLoader::load_from_str(content).map_err(|err| err.to_string())?;
+    let doc = match docs.into_iter().next() {
+        Some(doc) => doc,
+        None => return Ok(HashMap::new()),
+    };
+    let hash = match doc {
+        Yaml::Hash(hash) => hash,
+        Yaml::Null => return Ok(HashMap::new()),
+        _ => return Err("expected a mapping at the top level".to_string()),
+    };
+
+    hash.iter()
+        .map(|(key, value)| {
+            let key = key
+                .as_str()
+                .ok_or("keys must be strings".to_string())?;
+            let value = flatten_yaml(value)
+                .map_err(|err| format!("{} {}", key, err))?;
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Flattens the value the same way as `flatten_toml`.
+fn flatten_yaml(value: &Yaml) -> Result<String, String> {
+    match value {
+        Yaml::String(s) | Yaml::Real(s) => Ok(s.to_string()),
+        Yaml::Integer(i) => Ok(i.to_string()),
+        Yaml::Boolean(b) => Ok(b.to_string()),
+        Yaml::Null => Ok("".to_string()),
+        Yaml::Array(values) => {
+            let values = values
+                .iter()
+                .map(flatten_yaml)
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(values.join(","))
+        }
+        Yaml::Hash(hash) => {
+            let values = hash
+                .iter()
+                .map(|(k, v)| {
+                    let k = flatten_yaml(k)?;
+                    Ok(format!("{}={}", k, flatten_yaml(v)?))
+                })
+                .collect::<Result<Vec<String>, String>>()?;
+            Ok(values.join(","))
+        }
+        _ => Err("has an unsupported value".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() {
+        let values = parse_toml(
+            r#"
+min_threshold = 85.5
+use_github_api_for_blame = true
+coverage_files = ["a.xml", "b.xml"]
+"#,
+        )
+        .unwrap();
+        assert_eq!("85.5", values["min_threshold"]);
+        assert_eq!("true", values["use_github_api_for_blame"]);
+        assert_eq!("a.xml,b.xml", values["coverage_files"]);
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let values = parse_yaml(
+            r#"
+min_threshold: 85.5
+directory_depth: 2
+coverage_files:
+  - a.xml
+  - b.xml
+"#,
+        )
+        .unwrap();
+        assert_eq!("85.5", values["min_threshold"]);
+        assert_eq!("2", values["directory_depth"]);
+        assert_eq!("a.xml,b.xml", values["coverage_files"]);
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_keys() {
+        let path = std::env::temp_dir().join("committer-coverage-test-unknown.toml");
+        fs::write(&path, "min_threshold = 80\nmin_treshold = 90\n").unwrap();
+
+        let result = load(&path, &["min_threshold"]);
+
+        fs::remove_file(&path).unwrap();
+        let err = result.unwrap_err();
+        assert!(err.contains("unknown key min_treshold"), "{}", err);
+    }
+}
@@ -68,6 +68,13 @@ impl Default for FileCoverage {
 }
 
 impl FileCoverage {
+    pub fn new(path: &str) -> FileCoverage {
+        FileCoverage {
+            path: path.to_string(),
+            lines: BTreeMap::new(),
+        }
+    }
+
     pub fn get_path(&self) -> &str {
         &self.path
     }
@@ -79,10 +79,6 @@ impl CoverageFileIterator {
                 self.dec_level();
                 res
             }
-            Ok(Event::Text(e)) => {
-                println!("{:?}", e.unescape().unwrap());
-                ReadEventReturn::Continue
-            }
             Ok(Event::Eof) => ReadEventReturn::End,
             _ => ReadEventReturn::Continue,
         }
@@ -1,17 +1,61 @@
 //! This file will analyze the codebase.
-use git2::{Blame, BlameHunk};
+use git2::{Blame, BlameHunk, Oid};
 use mockall::automock;
-use std::{collections::BTreeMap, path::Path};
+use cache::BlameCache;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+pub mod cache;
+pub mod working_tree;
 
 /// Provides blame.
 #[automock]
 pub trait BlameProvider {
     fn get_file_blame(&self, path: &str) -> Result<BlameFile, String>;
+
+    /// The number of files `get_files_blame` blames at once.
+    fn get_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Blames the files, the results are in the order of the paths.
+    fn get_files_blame(&self, paths: &[String]) -> Vec<Result<BlameFile, String>> {
+        paths.iter().map(|path| self.get_file_blame(path)).collect()
+    }
+}
+
+/// Lets a shared provider, e.g. the GitHub client, be used from many threads.
+impl<T: BlameProvider + ?Sized> BlameProvider for &T {
+    fn get_file_blame(&self, path: &str) -> Result<BlameFile, String> {
+        (**self).get_file_blame(path)
+    }
+
+    fn get_batch_size(&self) -> usize {
+        (**self).get_batch_size()
+    }
+
+    fn get_files_blame(&self, paths: &[String]) -> Vec<Result<BlameFile, String>> {
+        (**self).get_files_blame(paths)
+    }
 }
 
 pub struct Git {
     path: String,
     repo: git2::Repository,
+    /// The commits already looked up, a commit is usually in many hunks.
+    commits: RefCell<HashMap<Oid, Arc<BlameCommit>>>,
+    /// The authors of the commits, shared by all their commits.
+    authors: RefCell<HashSet<Arc<BlameAuthor>>>,
+    /// Maps the authors to their canonical identity, like `git blame`.
+    mailmap: Option<git2::Mailmap>,
+    mailmap_hash: String,
+    blame_cache: Option<BlameCache>,
+    /// The commit the lines changed in the working tree are attributed to.
+    working_tree_commit: Option<Arc<BlameCommit>>,
 }
 
 impl Git {
@@ -33,11 +77,34 @@ impl Git {
             })?;
         }
 
+        Git::reopen_from_path(path)
+    }
+
+    /// Returns the time of the commit of the ref, e.g. a tag or a branch.
+    pub fn get_commit_time(&self, ref_name: &str) -> Result<i64, String> {
+        let commit = self
+            .repo
+            .revparse_single(ref_name)
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|err| format!("Failed to find commit of {}: {}", ref_name, err))?;
+        Ok(commit.time().seconds())
+    }
+
+    /// Opens another handle of a repository already opened with `new_from_path`,
+    /// e.g. one per blame thread, without changing the owner validation again.
+    pub fn reopen_from_path(path: &str) -> Result<Git, String> {
         let repo = git2::Repository::open(path)
             .map_err(|err| format!("Failed to open git repository: {}", err))?;
+        let mailmap = repo.mailmap().ok();
         Ok(Git {
             path: path.to_string(),
             repo,
+            commits: RefCell::new(HashMap::new()),
+            authors: RefCell::new(HashSet::new()),
+            mailmap,
+            mailmap_hash: String::new(),
+            blame_cache: None,
+            working_tree_commit: None,
         })
     }
 
@@ -45,6 +112,12 @@ impl Git {
         &self.path
     }
 
+    /// Returns whether the repository is a shallow clone. Its blame
+    /// attributes the lines of older commits to the oldest fetched commit.
+    pub fn is_shallow(&self) -> bool {
+        self.repo.is_shallow()
+    }
+
     pub fn get_last_commit_hash(&self) -> Result<String, String> {
         let obj = self
             .repo
@@ -61,6 +134,27 @@ impl Git {
 
 impl BlameProvider for Git {
     fn get_file_blame(&self, path: &str) -> Result<BlameFile, String> {
+        match &self.working_tree_commit {
+            Some(commit) => self.get_working_tree_blame(path, commit),
+            None => self.get_head_file_blame(path),
+        }
+    }
+}
+
+/// Supporting helper for BlameProvider trait.
+impl Git {
+    /// Returns the blame of the file at HEAD, from the cache if enabled.
+    fn get_head_file_blame(&self, path: &str) -> Result<BlameFile, String> {
+        let cache_key = match self.blame_cache {
+            Some(_) => Some(self.get_blame_cache_key(path)?),
+            None => None,
+        };
+        if let Some(blame_file) =
+            cache_key.as_ref().and_then(|key| self.load_cached_blame(key))
+        {
+            return Ok(blame_file);
+        }
+
         // we don't use ref_name for now
         let blame = self.load_repo_blame_from_path(path)?;
         let mut blame_file = BlameFile::new_from_path(path);
@@ -71,12 +165,13 @@ impl BlameProvider for Git {
                 self.iterate_blame_hunk(&hunk, &mut line_num, &mut blame_file);
             res?
         }
+
+        if let Some(key) = &cache_key {
+            self.store_cached_blame(key, &blame_file);
+        }
         Ok(blame_file)
     }
-}
 
-/// Supporting helper for BlameProvider trait.
-impl Git {
     fn load_repo_blame_from_path(
         &self,
         path: &str,
@@ -97,41 +192,148 @@ impl Git {
         line_num: &mut u32,
         blame_file: &mut BlameFile,
     ) -> Result<(), String> {
-        let commit_id = hunk.final_commit_id();
+        let commit = self.find_blame_commit(hunk.final_commit_id())?;
+
+        let num_lines = hunk.lines_in_hunk();
+
+        for _i in 0..num_lines {
+            *line_num += 1;
+            blame_file.add_commit_line(*line_num, &commit);
+        }
+        Ok(())
+    }
+
+    /// Returns the commit from the cache, or looks it up and caches it.
+    fn find_blame_commit(&self, commit_id: Oid) -> Result<Arc<BlameCommit>, String> {
+        if let Some(commit) = self.commits.borrow().get(&commit_id) {
+            return Ok(commit.clone());
+        }
 
         let commit = self
             .repo
             .find_commit(commit_id)
             .map_err(|err| format!("Failed to find commit: {}", err))?;
 
-        let author = commit.author();
+        let author = match &self.mailmap {
+            Some(mailmap) => commit
+                .author_with_mailmap(mailmap)
+                .map_err(|err| format!("Failed to map author: {}", err))?,
+            None => commit.author(),
+        };
         let email : Option<String> = match author.email() {
             Some(email) => Some(email.to_string()),
             None => {
-                eprintln!("Failed to get email from author when iterating blame hunk");
+                eprintln!("Failed to get email from author of commit {}", commit_id);
                 None
             }
         };
         let name: Option<String> = match author.name() {
             Some(name) => Some(name.to_string()),
             None => {
-                eprintln!("Failed to get name from author when iterating blame hunk");
+                eprintln!("Failed to get name from author of commit {}", commit_id);
                 None
             }
         };
+        let trailers = match commit.message() {
+            Some(message) => parse_trailers(message),
+            None => Vec::new(),
+        };
 
-        let num_lines = hunk.lines_in_hunk();
+        let blame_commit = Arc::new(BlameCommit {
+            id: commit_id.to_string(),
+            author: self.intern_author(BlameAuthor { email, name }),
+            time: Some(commit.time().seconds()),
+            trailers,
+        });
+        self.commits
+            .borrow_mut()
+            .insert(commit_id, blame_commit.clone());
+        Ok(blame_commit)
+    }
 
-        for _i in 0..num_lines {
-            *line_num += 1;
-            blame_file.add_line(
-                *line_num,
-                commit_id.to_string().as_str(),
-                email.clone(),
-                name.clone(),
-            )
+    /// Returns the shared author if there is one, so that the same
+    /// email and name are only stored once.
+    fn intern_author(&self, author: BlameAuthor) -> Arc<BlameAuthor> {
+        let mut authors = self.authors.borrow_mut();
+        if let Some(author) = authors.get(&author) {
+            return author.clone();
         }
-        Ok(())
+        let author = Arc::new(author);
+        authors.insert(author.clone());
+        author
+    }
+}
+
+/// Parses the trailers of a commit message, e.g. `Co-authored-by`.
+fn parse_trailers(message: &str) -> Vec<(String, String)> {
+    match git2::message_trailers_strs(message) {
+        Ok(trailers) => trailers
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The author of a commit, shared by all the lines blamed to the author.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlameAuthor {
+    email: Option<String>,
+    name: Option<String>,
+}
+
+impl BlameAuthor {
+    pub fn new(email: Option<String>, name: Option<String>) -> BlameAuthor {
+        BlameAuthor { email, name }
+    }
+
+    pub fn get_email(&self) -> &Option<String> {
+        &self.email
+    }
+
+    pub fn get_name(&self) -> &Option<String> {
+        &self.name
+    }
+}
+
+/// A commit lines are blamed to, shared by all its lines.
+#[derive(Debug)]
+pub struct BlameCommit {
+    id: String,
+    author: Arc<BlameAuthor>,
+    /// The commit time in seconds since the epoch, if known.
+    time: Option<i64>,
+    trailers: Vec<(String, String)>,
+}
+
+impl BlameCommit {
+    pub fn new(id: &str, author: Arc<BlameAuthor>) -> BlameCommit {
+        BlameCommit {
+            id: id.to_string(),
+            author,
+            time: None,
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_author(&self) -> &Arc<BlameAuthor> {
+        &self.author
+    }
+
+    pub fn get_time(&self) -> Option<i64> {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: Option<i64>) {
+        self.time = time;
+    }
+
+    pub fn get_trailers(&self) -> &Vec<(String, String)> {
+        &self.trailers
     }
 }
 
@@ -159,6 +361,11 @@ impl BlameFile {
         self.lines.insert(line, BlameLine::new(line, commit, email, name));
     }
 
+    /// Adds a line blamed to a shared commit.
+    pub fn add_commit_line(&mut self, line: u32, commit: &Arc<BlameCommit>) {
+        self.lines.insert(line, BlameLine::new_from_commit(line, commit));
+    }
+
     pub fn set_lines_from_vec(&mut self, lines: Vec<BlameLine>) {
         self.lines = lines.into_iter().map(|line| (line.get_line(), line)).collect();
     }
@@ -166,55 +373,255 @@ impl BlameFile {
 
 pub struct BlameLine {
     line: u32,
-    commit: String,
-    email: Option<String>,
-    name: Option<String>,
+    commit: Arc<BlameCommit>,
 }
 
 impl BlameLine {
     pub fn new(line: u32, commit: &str, email: Option<String>, name: Option<String>) -> BlameLine {
+        let author = Arc::new(BlameAuthor::new(email, name));
         BlameLine {
             line,
-            commit: commit.to_string(),
-            email,
-            name,
+            commit: Arc::new(BlameCommit::new(commit, author)),
         }
     }
+
+    pub fn new_from_commit(line: u32, commit: &Arc<BlameCommit>) -> BlameLine {
+        BlameLine {
+            line,
+            commit: commit.clone(),
+        }
+    }
+
     pub fn get_line(&self) -> u32 {
         self.line
     }
 
     pub fn get_commit(&self) -> &str {
+        self.commit.get_id()
+    }
+
+    pub fn get_blame_commit(&self) -> &Arc<BlameCommit> {
         &self.commit
     }
 
+    /// The time of the commit in seconds since the epoch, if known.
+    pub fn get_time(&self) -> Option<i64> {
+        self.commit.get_time()
+    }
+
     pub fn get_email(&self) -> &Option<String> {
-        &self.email
+        self.commit.get_author().get_email()
     }
 
     pub fn must_get_email(&self) -> String {
-        self.email.clone().unwrap_or("unknown".to_string())
+        self.get_email().clone().unwrap_or("unknown".to_string())
     }
 
     pub fn get_name(&self) -> &Option<String> {
-        &self.name
+        self.commit.get_author().get_name()
     }
 
     pub fn must_get_name(&self) -> String {
-        self.name.clone().unwrap_or("unknown".to_string())
+        self.get_name().clone().unwrap_or("unknown".to_string())
     }
 }
 
 impl std::fmt::Display for BlameLine {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let email = match &self.email {
+        let email = match self.get_email() {
             Some(email) => email,
             None => "",
         };
-        let name = match &self.name {
+        let name = match self.get_name() {
             Some(name) => name,
             None => "",
         };
-        write!(f, "{}: {} ({} <{}>)", self.line, self.commit, name, email)
+        write!(f, "{}: {} ({} <{}>)", self.line, self.get_commit(), name, email)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn commit_file(repo: &git2::Repository, content: &str, message: &str) {
+        let path = repo.workdir().unwrap().join("file.txt");
+        fs::write(path, content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature =
+            git2::Signature::new("User", "user@example.com", &git2::Time::new(1700000000, 0))
+                .unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_git_get_file_blame_shares_commits_and_authors() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-git-blame");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(&dir).unwrap();
+        commit_file(&repo, "a\nb\n", "First");
+        commit_file(
+            &repo,
+            "a\nb\nc\nd\n",
+            "Second\n\nCo-authored-by: Other <other@example.com>\n",
+        );
+
+        let git = Git::new_from_path(dir.to_str().unwrap()).unwrap();
+        let blame_file = git.get_file_blame("file.txt").unwrap();
+        let lines = blame_file.get_lines();
+        assert_eq!(4, lines.len());
+
+        let (first, second) = (&lines[&1], &lines[&3]);
+        assert_ne!(first.get_commit(), second.get_commit());
+        assert!(Arc::ptr_eq(first.get_blame_commit(), lines[&2].get_blame_commit()));
+        assert!(Arc::ptr_eq(second.get_blame_commit(), lines[&4].get_blame_commit()));
+        // both commits have the same author.
+        assert!(Arc::ptr_eq(
+            first.get_blame_commit().get_author(),
+            second.get_blame_commit().get_author()
+        ));
+        assert_eq!(&Some("user@example.com".to_string()), second.get_email());
+        assert_eq!(Some(1700000000), second.get_blame_commit().get_time());
+        assert_eq!(
+            &vec![(
+                "Co-authored-by".to_string(),
+                "Other <other@example.com>".to_string()
+            )],
+            second.get_blame_commit().get_trailers()
+        );
+        assert!(first.get_blame_commit().get_trailers().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_get_file_blame_from_cache() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-git-blame-cache");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(dir.join("repo")).unwrap();
+        commit_file(&repo, "a\nb\n", "First");
+        let repo_path = dir.join("repo");
+        let cache_dir = dir.join("cache");
+
+        let blame = || {
+            let mut git = Git::new_from_path(repo_path.to_str().unwrap()).unwrap();
+            git.set_blame_cache(BlameCache::new(cache_dir.to_str().unwrap()));
+            git.get_file_blame("file.txt").unwrap()
+        };
+        let blame_file = blame();
+        assert_eq!(1, fs::read_dir(&cache_dir).unwrap().count());
+
+        let cached = blame();
+        assert_eq!(2, cached.get_lines().len());
+        for (line_num, line) in blame_file.get_lines() {
+            let cached_line = &cached.get_lines()[line_num];
+            assert_eq!(line.to_string(), cached_line.to_string());
+            assert_eq!(
+                line.get_blame_commit().get_time(),
+                cached_line.get_blame_commit().get_time()
+            );
+        }
+
+        // a changed file is blamed again.
+        commit_file(&repo, "a\nb\nc\n", "Second");
+        assert_eq!(3, blame().get_lines().len());
+        assert_eq!(2, fs::read_dir(&cache_dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_get_file_blame_ignores_corrupt_cache_entry() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-git-blame-cache-corrupt");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(dir.join("repo")).unwrap();
+        commit_file(&repo, "a\nb\n", "First");
+        let cache_dir = dir.join("cache");
+
+        let mut git = Git::new_from_path(dir.join("repo").to_str().unwrap()).unwrap();
+        git.set_blame_cache(BlameCache::new(cache_dir.to_str().unwrap()));
+        let key = git.get_blame_cache_key("file.txt").unwrap();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let blob = tree.get_name("file.txt").unwrap().id().to_string();
+        let entry = json::object! {
+            "version" => cache::BLAME_CACHE_VERSION,
+            "path" => "file.txt",
+            "blob" => blob,
+            "mailmap" => git.mailmap_hash.as_str(),
+            "commits" => json::array![json::array![
+                "8d5445550b1948b914853fc7f210ff3622ee0c18", null, null, null, json::array![]
+            ]],
+            "ranges" => json::array![json::array![2, u32::MAX, 0]],
+        };
+        BlameCache::new(cache_dir.to_str().unwrap()).store(&key, &entry).unwrap();
+
+        // a range past the last line is a cache miss, the file is blamed again.
+        let blame_file = git.get_file_blame("file.txt").unwrap();
+        assert_eq!(2, blame_file.get_lines().len());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_get_file_blame_of_shallow_clone_is_not_cached() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-git-blame-cache-shallow");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(dir.join("repo")).unwrap();
+        commit_file(&repo, "a\nb\n", "First");
+        let head = repo.head().unwrap().target().unwrap();
+        fs::write(dir.join("repo/.git/shallow"), format!("{}\n", head)).unwrap();
+        let cache_dir = dir.join("cache");
+
+        let mut git = Git::new_from_path(dir.join("repo").to_str().unwrap()).unwrap();
+        assert!(git.is_shallow());
+        git.set_blame_cache(BlameCache::new(cache_dir.to_str().unwrap()));
+        assert_eq!(2, git.get_file_blame("file.txt").unwrap().get_lines().len());
+        assert!(!cache_dir.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_get_file_blame_of_working_tree() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-git-blame-working-tree");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(&dir).unwrap();
+        commit_file(&repo, "a\nb\nc\n", "First");
+        fs::write(dir.join("file.txt"), "a\nx\nc\nd\n").unwrap();
+        fs::write(dir.join("new.txt"), "n\n").unwrap();
+        fs::write(dir.join(".gitignore"), "generated.txt\n").unwrap();
+        fs::write(dir.join("generated.txt"), "g\n").unwrap();
+
+        let mut git = Git::new_from_path(dir.to_str().unwrap()).unwrap();
+        // without an author the blame is the one at HEAD.
+        assert_eq!(3, git.get_file_blame("file.txt").unwrap().get_lines().len());
+
+        git.set_working_tree_author(BlameAuthor::new(
+            Some("author@example.com".to_string()),
+            None,
+        ));
+        let blame_file = git.get_file_blame("file.txt").unwrap();
+        let lines = blame_file.get_lines();
+        assert_eq!(4, lines.len());
+        assert_eq!(&Some("user@example.com".to_string()), lines[&1].get_email());
+        assert_eq!(working_tree::UNCOMMITTED_COMMIT_ID, lines[&2].get_commit());
+        assert_eq!(&Some("author@example.com".to_string()), lines[&2].get_email());
+        assert_eq!(&Some("user@example.com".to_string()), lines[&3].get_email());
+        assert_eq!(&Some("author@example.com".to_string()), lines[&4].get_email());
+
+        // an untracked file is attributed, an ignored one is not.
+        let blame_file = git.get_file_blame("new.txt").unwrap();
+        assert_eq!(&Some("author@example.com".to_string()), blame_file.get_lines()[&1].get_email());
+        let err = git.get_file_blame("generated.txt").err().unwrap();
+        assert!(err.contains("not exist in the given tree"), "{}", err);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,294 @@
+//! Caches the blame of files between runs, e.g. restored with actions/cache.
+//! A file is only blamed again if its content at HEAD or the mailmap changed.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use git2::{ObjectType, Oid};
+use json::{array, object, JsonValue};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{BlameAuthor, BlameCommit, BlameFile, Git};
+
+/// The version of the cache entry schema.
+pub const BLAME_CACHE_VERSION: u32 = 1;
+
+/// The directory of the cached blames, one compressed file per entry.
+pub struct BlameCache {
+    dir: PathBuf,
+}
+
+/// Identifies the blame of a file: the same path, content and mailmap
+/// give the same blame.
+#[derive(Debug, PartialEq)]
+pub struct BlameCacheKey {
+    path: String,
+    blob: String,
+    mailmap: String,
+}
+
+impl BlameCacheKey {
+    pub fn new(path: &str, blob: &str, mailmap: &str) -> BlameCacheKey {
+        BlameCacheKey {
+            path: path.to_string(),
+            blob: blob.to_string(),
+            mailmap: mailmap.to_string(),
+        }
+    }
+
+    /// The file name of the entry, a hash of the key.
+    fn get_file_name(&self) -> String {
+        let key = format!("{}\n{}\n{}", self.path, self.blob, self.mailmap);
+        let hash = Oid::hash_object(ObjectType::Blob, key.as_bytes())
+            .map_or(String::new(), |oid| oid.to_string());
+        format!("{}.json.gz", hash)
+    }
+}
+
+impl BlameCache {
+    pub fn new(dir: &str) -> BlameCache {
+        BlameCache {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn get_entry_path(&self, key: &BlameCacheKey) -> PathBuf {
+        self.dir.join(key.get_file_name())
+    }
+
+    /// Loads the entry of the key, or None if there is none or it is invalid.
+    pub fn load(&self, key: &BlameCacheKey) -> Option<JsonValue> {
+        let file = fs::File::open(self.get_entry_path(key)).ok()?;
+        let mut content = String::new();
+        GzDecoder::new(file).read_to_string(&mut content).ok()?;
+        let json = json::parse(&content).ok()?;
+
+        // the hash of another key may collide.
+        let matches = json["version"].as_u32() == Some(BLAME_CACHE_VERSION)
+            && json["path"] == key.path.as_str()
+            && json["blob"] == key.blob.as_str()
+            && json["mailmap"] == key.mailmap.as_str();
+        if !matches {
+            return None;
+        }
+        Some(json)
+    }
+
+    /// Stores the entry of the key, replacing the file only once it is written,
+    /// so that an interrupted run does not leave a broken entry.
+    pub fn store(&self, key: &BlameCacheKey, entry: &JsonValue) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|err| {
+            format!("Failed to create blame cache {}: {}", self.dir.display(), err)
+        })?;
+
+        let path = self.get_entry_path(key);
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        let write = || -> std::io::Result<()> {
+            let file = fs::File::create(&tmp_path)?;
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            encoder.write_all(entry.dump().as_bytes())?;
+            encoder.finish()?;
+            fs::rename(&tmp_path, &path)
+        };
+        write().map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            format!("Failed to write blame cache {}: {}", path.display(), err)
+        })
+    }
+}
+
+/// Creates the cache entry of the blame.
+///
+/// The schema is:
+/// ```json
+/// {
+///   "version": 1,
+///   "path": "src/lib.rs",
+///   "blob": "<blob id>",
+///   "mailmap": "<mailmap hash>",
+///   "commits": [["<commit id>", "user@example.com", "User", 1700000000, [["Co-authored-by", "..."]]]],
+///   "ranges": [[1, 10, 0]]
+/// }
+/// ```
+/// A range is the first line, the number of lines and the index of the commit.
+fn create_entry(key: &BlameCacheKey, blame_file: &BlameFile) -> JsonValue {
+    let mut commits = JsonValue::new_array();
+    let mut indexes: HashMap<&str, usize> = HashMap::new();
+    let mut ranges: Vec<(u32, u32, usize)> = Vec::new();
+
+    for (line_num, line) in blame_file.get_lines() {
+        let commit = line.get_blame_commit();
+        let index = *indexes.entry(commit.get_id()).or_insert_with(|| {
+            let author = commit.get_author();
+            let trailers: Vec<JsonValue> = commit
+                .get_trailers()
+                .iter()
+                .map(|(key, value)| array![key.as_str(), value.as_str()])
+                .collect();
+            commits
+                .push(array![
+                    commit.get_id(),
+                    author.get_email().clone(),
+                    author.get_name().clone(),
+                    commit.get_time(),
+                    trailers
+                ])
+                .unwrap();
+            commits.len() - 1
+        });
+
+        match ranges.last_mut() {
+            Some((start, count, last)) if *last == index && *start + *count == *line_num => {
+                *count += 1
+            }
+            _ => ranges.push((*line_num, 1, index)),
+        }
+    }
+
+    let ranges: Vec<JsonValue> = ranges
+        .into_iter()
+        .map(|(start, count, index)| array![start, count, index])
+        .collect();
+    object! {
+        "version" => BLAME_CACHE_VERSION,
+        "path" => key.path.as_str(),
+        "blob" => key.blob.as_str(),
+        "mailmap" => key.mailmap.as_str(),
+        "commits" => commits,
+        "ranges" => ranges,
+    }
+}
+
+impl Git {
+    /// Caches the blame of the files in the directory. The blame of a shallow
+    /// clone depends on the fetched history, not only on the key, so it is
+    /// neither loaded from nor stored in the cache.
+    pub fn set_blame_cache(&mut self, cache: BlameCache) {
+        if self.is_shallow() {
+            return;
+        }
+        self.mailmap_hash = hash_mailmap(&self.repo);
+        self.blame_cache = Some(cache);
+    }
+
+    /// Returns the key of the file at HEAD.
+    pub(super) fn get_blame_cache_key(&self, path: &str) -> Result<BlameCacheKey, String> {
+        let blob = self
+            .repo
+            .head()
+            .and_then(|head| head.peel_to_tree())
+            .and_then(|tree| tree.get_path(Path::new(path)))
+            .map_err(|err| format!("Failed to get blame: {}", err))?
+            .id();
+        Ok(BlameCacheKey::new(path, &blob.to_string(), &self.mailmap_hash))
+    }
+
+    /// Loads the blame from the cache. The commits are shared with the
+    /// commits already looked up, like the blame of `get_file_blame`.
+    pub(super) fn load_cached_blame(&self, key: &BlameCacheKey) -> Option<BlameFile> {
+        let entry = self.blame_cache.as_ref()?.load(key)?;
+
+        let mut commits = Vec::new();
+        for commit in entry["commits"].members() {
+            let id = commit[0].as_str()?;
+            let oid = Oid::from_str(id).ok()?;
+            let cached = self.commits.borrow().get(&oid).cloned();
+            let commit = match cached {
+                Some(cached) => cached,
+                None => {
+                    let author = self.intern_author(BlameAuthor::new(
+                        commit[1].as_str().map(|email| email.to_string()),
+                        commit[2].as_str().map(|name| name.to_string()),
+                    ));
+                    let mut blame_commit = BlameCommit::new(id, author);
+                    blame_commit.set_time(commit[3].as_i64());
+                    for trailer in commit[4].members() {
+                        blame_commit.trailers.push((
+                            trailer[0].as_str()?.to_string(),
+                            trailer[1].as_str()?.to_string(),
+                        ));
+                    }
+                    let blame_commit = Arc::new(blame_commit);
+                    self.commits.borrow_mut().insert(oid, blame_commit.clone());
+                    blame_commit
+                }
+            };
+            commits.push(commit);
+        }
+
+        let mut blame_file = BlameFile::new_from_path(&key.path);
+        for range in entry["ranges"].members() {
+            let start = range[0].as_u32()?;
+            let count = range[1].as_u32()?;
+            let commit = commits.get(range[2].as_usize()?)?;
+            for line in start..start.checked_add(count)? {
+                blame_file.add_commit_line(line, commit);
+            }
+        }
+        Some(blame_file)
+    }
+
+    /// Stores the blame in the cache, a failure only skips the caching.
+    pub(super) fn store_cached_blame(&self, key: &BlameCacheKey, blame_file: &BlameFile) {
+        if let Some(cache) = &self.blame_cache {
+            if let Err(err) = cache.store(key, &create_entry(key, blame_file)) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+}
+
+/// Hashes what the mailmap is read from: the `.mailmap` file and the
+/// `mailmap.file` and `mailmap.blob` settings.
+fn hash_mailmap(repo: &git2::Repository) -> String {
+    let mut content = Vec::new();
+    if let Some(workdir) = repo.workdir() {
+        if let Ok(mailmap) = fs::read(workdir.join(".mailmap")) {
+            content.extend(mailmap);
+        }
+    }
+    if let Ok(config) = repo.config() {
+        for name in ["mailmap.file", "mailmap.blob"] {
+            if let Ok(value) = config.get_string(name) {
+                content.extend(format!("\n{}={}", name, value).into_bytes());
+            }
+        }
+    }
+    Oid::hash_object(ObjectType::Blob, &content).map_or(String::new(), |oid| oid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blame_cache_store_and_load() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-blame-cache");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = BlameCache::new(dir.to_str().unwrap());
+
+        let mut blame_file = BlameFile::new_from_path("src/lib.rs");
+        blame_file.add_line(1, "abc", Some("user@example.com".to_string()), None);
+        blame_file.add_line(2, "abc", Some("user@example.com".to_string()), None);
+        blame_file.add_line(3, "def", None, Some("Other".to_string()));
+        blame_file.add_line(4, "abc", Some("user@example.com".to_string()), None);
+
+        let key = BlameCacheKey::new("src/lib.rs", "blob", "mailmap");
+        let entry = create_entry(&key, &blame_file);
+        assert_eq!(2, entry["commits"].len());
+        assert_eq!("[[1,2,0],[3,1,1],[4,1,0]]", entry["ranges"].dump());
+
+        assert!(cache.load(&key).is_none());
+        cache.store(&key, &entry).unwrap();
+        assert_eq!(Some(entry), cache.load(&key));
+
+        // another blob or mailmap is another entry.
+        assert!(cache.load(&BlameCacheKey::new("src/lib.rs", "blob2", "mailmap")).is_none());
+        assert!(cache.load(&BlameCacheKey::new("src/lib.rs", "blob", "mailmap2")).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
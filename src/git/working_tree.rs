@@ -0,0 +1,180 @@
+//! Attributes the lines changed in the working tree, which `git blame`
+//! at HEAD does not know, to a configured identity, e.g. the author of
+//! the pull request.
+use git2::{DiffOptions, Patch};
+use std::{collections::HashSet, fs, path::Path, sync::Arc};
+
+use super::{BlameAuthor, BlameCommit, BlameFile, Git};
+
+/// The commit id of the lines that are not committed yet, like `git blame`.
+pub const UNCOMMITTED_COMMIT_ID: &str = "0000000000000000000000000000000000000000";
+
+/// Parses an identity like `Name <user@example.com>` or `user@example.com`.
+pub fn parse_identity(value: &str) -> Result<BlameAuthor, String> {
+    let invalid = || format!("Invalid identity: {}", value);
+    let value = value.trim();
+    let (name, email) = match value.strip_suffix('>') {
+        Some(value) => {
+            let (name, email) = value.rsplit_once('<').ok_or_else(invalid)?;
+            let name = name.trim();
+            (
+                if name.is_empty() { None } else { Some(name) },
+                email.trim(),
+            )
+        }
+        None => (None, value),
+    };
+    if !email.contains('@') || email.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok(BlameAuthor::new(
+        Some(email.to_string()),
+        name.map(|name| name.to_string()),
+    ))
+}
+
+impl Git {
+    /// Attributes the lines changed in the working tree, and the files that
+    /// are not committed and not ignored, to the author.
+    pub fn set_working_tree_author(&mut self, author: BlameAuthor) {
+        let author = self.intern_author(author);
+        let commit = BlameCommit::new(UNCOMMITTED_COMMIT_ID, author);
+        self.working_tree_commit = Some(Arc::new(commit));
+    }
+
+    /// Returns the blame of the file in the working tree, based on the blame
+    /// at HEAD. The lines of HEAD are moved to their line in the working tree,
+    /// the added lines are attributed to the commit.
+    pub(super) fn get_working_tree_blame(
+        &self,
+        path: &str,
+        commit: &Arc<BlameCommit>,
+    ) -> Result<BlameFile, String> {
+        let workdir = match self.repo.workdir() {
+            Some(workdir) => workdir,
+            None => return self.get_head_file_blame(path),
+        };
+        // a file deleted in the working tree has only stale coverage.
+        let content = match fs::read(workdir.join(path)) {
+            Ok(content) => content,
+            Err(_) => return self.get_head_file_blame(path),
+        };
+
+        let (head_blame, head_content) = match self.get_head_file_blame(path) {
+            Ok(head_blame) => (head_blame, self.get_head_file_content(path)?),
+            // a generated file is left unattributed.
+            Err(err) if err.contains("not exist in the given tree") => {
+                let ignored = self
+                    .repo
+                    .status_should_ignore(Path::new(path))
+                    .unwrap_or(true);
+                if ignored {
+                    return Err(err);
+                }
+                (BlameFile::new_from_path(path), Vec::new())
+            }
+            Err(err) => return Err(err),
+        };
+        if head_content == content {
+            return Ok(head_blame);
+        }
+
+        let (added, deleted) = diff_lines(&head_content, &content)?;
+        let mut blame_file = BlameFile::new_from_path(path);
+        let mut head_line = 1;
+        for line in 1..=count_lines(&content) {
+            if added.contains(&line) {
+                blame_file.add_commit_line(line, commit);
+                continue;
+            }
+            while deleted.contains(&head_line) {
+                head_line += 1;
+            }
+            if let Some(blame_line) = head_blame.get_lines().get(&head_line) {
+                blame_file.add_commit_line(line, blame_line.get_blame_commit());
+            }
+            head_line += 1;
+        }
+        Ok(blame_file)
+    }
+
+    fn get_head_file_content(&self, path: &str) -> Result<Vec<u8>, String> {
+        let blob = self
+            .repo
+            .head()
+            .and_then(|head| head.peel_to_tree())
+            .and_then(|tree| tree.get_path(Path::new(path)))
+            .and_then(|entry| entry.to_object(&self.repo))
+            .and_then(|object| object.peel_to_blob())
+            .map_err(|err| format!("Failed to read {} at HEAD: {}", path, err))?;
+        Ok(blob.content().to_vec())
+    }
+}
+
+/// Returns the lines added to the new content and the lines deleted from
+/// the old content. The other lines are unchanged and in the same order.
+fn diff_lines(old: &[u8], new: &[u8]) -> Result<(HashSet<u32>, HashSet<u32>), String> {
+    let mut opts = DiffOptions::new();
+    opts.context_lines(0);
+    let patch = Patch::from_buffers(old, None, new, None, Some(&mut opts))
+        .map_err(|err| format!("Failed to diff the working tree: {}", err))?;
+
+    let mut added = HashSet::new();
+    let mut deleted = HashSet::new();
+    for hunk in 0..patch.num_hunks() {
+        let lines = patch
+            .num_lines_in_hunk(hunk)
+            .map_err(|err| format!("Failed to diff the working tree: {}", err))?;
+        for i in 0..lines {
+            let line = patch
+                .line_in_hunk(hunk, i)
+                .map_err(|err| format!("Failed to diff the working tree: {}", err))?;
+            match (line.origin(), line.old_lineno(), line.new_lineno()) {
+                ('+', _, Some(new_line)) => added.insert(new_line),
+                ('-', Some(old_line), _) => deleted.insert(old_line),
+                _ => false,
+            };
+        }
+    }
+    Ok((added, deleted))
+}
+
+/// Counts the lines like git, the last line may not end with a newline.
+fn count_lines(content: &[u8]) -> u32 {
+    let newlines = content.iter().filter(|byte| **byte == b'\n').count();
+    match content.last() {
+        Some(b'\n') | None => newlines as u32,
+        Some(_) => newlines as u32 + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_identity() {
+        let author = parse_identity("Jane Doe <jane@example.com>").unwrap();
+        assert_eq!(&Some("jane@example.com".to_string()), author.get_email());
+        assert_eq!(&Some("Jane Doe".to_string()), author.get_name());
+
+        let author = parse_identity(" jane@example.com ").unwrap();
+        assert_eq!(&Some("jane@example.com".to_string()), author.get_email());
+        assert_eq!(&None, author.get_name());
+
+        assert!(parse_identity("Jane Doe").is_err());
+        assert!(parse_identity("Jane <jane@example.com").is_err());
+        assert!(parse_identity("").is_err());
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let (added, deleted) = diff_lines(b"a\nb\nc\nd\n", b"a\nx\nc\nd\ny\n").unwrap();
+        assert_eq!(HashSet::from([2, 5]), added);
+        assert_eq!(HashSet::from([2]), deleted);
+
+        assert_eq!(0, count_lines(b""));
+        assert_eq!(2, count_lines(b"a\nb\n"));
+        assert_eq!(2, count_lines(b"a\nb"));
+    }
+}
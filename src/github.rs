@@ -1,29 +1,51 @@
 //! This file contains the GitHub API client and its implementation.
 //use curl::easy::{Easy, List};
 
-use email_address::EmailAddress;
+use mockall::automock;
 use reqwest::{blocking::Client, StatusCode};
-use std::collections::HashMap;
+use std::sync::Mutex;
 
 use crate::{
-    analysis::CommitterCoverageUserStat,
-    git::{BlameFile, BlameLine, BlameProvider},
+    git::{BlameFile, BlameProvider},
+    time,
+    report::markdown::{MarkdownWriter, SummaryContentOptions},
 };
 
 use super::analysis;
 use json::object;
 
-enum GitHubUserCacheRecord {
-    Some(GithubUser),
-    None,
+pub mod app_auth;
+mod blame;
+pub mod check_run;
+pub mod commit_author;
+pub mod commit_status;
+pub mod event;
+mod http;
+pub mod user_cache;
+
+use app_auth::AppAuth;
+use user_cache::UserCache;
+
+/// Provides GitHub users.
+#[automock]
+pub trait UserProvider {
+    fn get_user_by_email(&self, email: &str)
+        -> Result<Option<GithubUser>, String>;
 }
+
 /// This struct represents the GitHub API client.
 pub struct GitHubClient {
     // default token
     token: String,
     api_url: String,
     repo: String,
-    user_cache: HashMap<String, GitHubUserCacheRecord>,
+    user_cache: Mutex<UserCache>,
+    // shared by all requests to reuse the connections.
+    client: Client,
+    // replaces the token if authenticated as a GitHub App.
+    app_auth: Option<AppAuth>,
+    // the commit the files are blamed at.
+    blame_ref: String,
 }
 
 const USER_AGENT: &str = "petrabarus/committer-coverage-summary";
@@ -35,45 +57,81 @@ impl GitHubClient {
         token: &str,
         //
     ) -> GitHubClient {
-        let user_cache = HashMap::new();
         GitHubClient {
             api_url: api_url.to_string(),
             repo: repo.to_string(),
             token: token.to_string(),
-            user_cache,
+            user_cache: Mutex::new(UserCache::default()),
+            client: Client::new(),
+            app_auth: None,
+            blame_ref: "HEAD".to_string(),
         }
     }
 
+    /// Sets the commit, or another git expression, the files are blamed at
+    /// with the GraphQL API. Defaults to `HEAD`, the default branch.
+    pub fn set_blame_ref(&mut self, blame_ref: &str) {
+        self.blame_ref = blame_ref.to_string();
+    }
+
+    /// Replaces the user cache, e.g. with one loaded from a file.
+    pub fn set_user_cache(&mut self, user_cache: UserCache) {
+        self.user_cache = Mutex::new(user_cache);
+    }
+
+    /// Writes the user cache to a file, to be loaded by the next run.
+    pub fn write_user_cache_to_file(&self, path: &str) -> Result<(), String> {
+        self.user_cache
+            .lock()
+            .unwrap()
+            .write_to_file(path, time::now())
+    }
+
     pub fn print_summary_to_pr(
         &self,
         pull_request_number: u32,
         summary: &analysis::CommitterCoverageSummary,
-        min_threshold: f32,
+        options: &SummaryContentOptions,
     ) -> Result<(), String> {
-        let body = self.create_summary_content(summary, min_threshold);
+        if let Err(err) =
+            self.resolve_users_by_commits(summary.get_commits_by_email())
+        {
+            eprintln!("Failed to resolve users by commits: {}", err);
+        }
+        let body = MarkdownWriter::new(Some(self))
+            .create_summary_content(summary, options);
         self.request_post_issue_comment(pull_request_number, &body)
     }
 
-    fn create_sync_client(&self) -> Client {
-        Client::new()
+    fn create_sync_client(&self) -> &Client {
+        &self.client
     }
 
     fn create_sync_post_client(
         &self,
         url: &str,
-    ) -> reqwest::blocking::RequestBuilder {
+    ) -> Result<reqwest::blocking::RequestBuilder, String> {
         let req = self.create_sync_client().post(url);
         let req = self.add_basic_headers_to_request(req);
-        self.add_bearer_token_to_request(req, &self.token)
+        Ok(self.add_bearer_token_to_request(req, &self.get_token()?))
     }
 
     fn create_sync_get_client(
         &self,
         url: &str,
-    ) -> reqwest::blocking::RequestBuilder {
+    ) -> Result<reqwest::blocking::RequestBuilder, String> {
         let req = self.create_sync_client().get(url);
         let req = self.add_basic_headers_to_request(req);
-        self.add_bearer_token_to_request(req, &self.token)
+        Ok(self.add_bearer_token_to_request(req, &self.get_token()?))
+    }
+
+    fn create_sync_patch_client(
+        &self,
+        url: &str,
+    ) -> Result<reqwest::blocking::RequestBuilder, String> {
+        let req = self.create_sync_client().patch(url);
+        let req = self.add_basic_headers_to_request(req);
+        Ok(self.add_bearer_token_to_request(req, &self.get_token()?))
     }
 
     fn add_basic_headers_to_request(
@@ -103,23 +161,34 @@ impl GitHubClient {
         let data = object! {
             "body" => body,
         };
-        let data = data.dump();
-
-        let req = self
-            .create_sync_post_client(&url)
-            .body(data);
-        let result = req.send();
-
-        match result {
-            Ok(result) => match result.status() {
-                StatusCode::CREATED => Ok(()),
-                status => Err(format!(
-                    "Failed to send request: {}",
-                    status.canonical_reason().unwrap_or("Unknown Status")
-                )),
-            },
-            Err(err) => Err(format!("Failed to send request: {}", err)),
-        }
+        self.send_request(
+            self.create_sync_post_client(&url)?,
+            data,
+            StatusCode::CREATED,
+        )?;
+        Ok(())
+    }
+
+    /// Get the sha of the head commit of a pull request.
+    pub fn get_pull_request_head_sha(
+        &self,
+        pull_request_number: u32,
+    ) -> Result<String, String> {
+        let url = format!(
+            "{}/repos/{}/pulls/{}",
+            self.api_url, self.repo, pull_request_number
+        );
+        let response = self.send_request(
+            self.create_sync_get_client(&url)?,
+            json::JsonValue::Null,
+            StatusCode::OK,
+        )?;
+        let json = json::parse(&response)
+            .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+        json["head"]["sha"]
+            .as_str()
+            .map(|sha| sha.to_string())
+            .ok_or_else(|| "Pull request response has no head sha".to_string())
     }
 
     fn create_pr_comment_url(&self, pull_request_number: u32) -> String {
@@ -130,18 +199,26 @@ impl GitHubClient {
     }
 
     /// Get a user by email.
-    /// This will check the cache first before making a request to the GitHub API.
+    /// This will check the cache first, see `resolve_users_by_commits`, and
+    /// parse noreply addresses before making a request to the GitHub API.
     /// If the user is not found, it will return None.
     /// If there is error in the request, it will return an error message.
     pub fn get_user_by_email(
         &self,
         email: &str,
     ) -> Result<Option<GithubUser>, String> {
+        if let Some(user) = self.get_cached_user(email) {
+            return Ok(user);
+        }
+        if let Some(user) = commit_author::parse_noreply_email(email) {
+            return Ok(Some(user));
+        }
+
         let user = self.request_search_user_by_email(email).map_err(|err| {
             format!("Failed to search user by email: {}", err)
         })?;
 
-        //self.cache_user(email, &user);
+        self.store_cache_user(email, &user);
 
         Ok(user)
     }
@@ -150,47 +227,40 @@ impl GitHubClient {
         &self,
         email: &str,
     ) -> Result<Option<GithubUser>, String> {
-        let url = format!("{}/search/users?q={}", self.api_url, email);
-
-        let req = self.create_sync_get_client(&url);
-        let result = req.send();
-
-        match result {
-            Ok(result) => match result.status() {
-                StatusCode::OK => {
-                    let response = result.text().map_err(|err| {
-                        format!("Failed to read response: {}", err)
-                    })?;
-                    GitHubClient::parse_user_from_search_response(&response)
-                }
-                status => Err(format!(
-                    "Failed to send request: {}",
-                    status.canonical_reason().unwrap_or("Unknown Status")
-                )),
-            },
-            Err(err) => Err(format!("Failed to send request: {}", err)),
-        }
-    }
+        let query = format!("{} in:email", email);
+        let url = format!(
+            "{}/search/users?q={}",
+            self.api_url,
+            encode_query_component(&query)
+        );
 
-    pub fn store_cache_user(&mut self, email: &str, user: &Option<GithubUser>) {
-        let record = match user {
-            Some(user) => GitHubUserCacheRecord::Some(user.clone()),
-            None => GitHubUserCacheRecord::None,
-        };
+        let response = self.send_request(
+            self.create_sync_get_client(&url)?,
+            json::JsonValue::Null,
+            StatusCode::OK,
+        )?;
+        GitHubClient::parse_user_from_search_response(&response)
+    }
 
-        self.user_cache.insert(email.to_string(), record);
+    /// Stores the search result of an email, including that no user was found.
+    pub fn store_cache_user(&self, email: &str, user: &Option<GithubUser>) {
+        self.user_cache
+            .lock()
+            .unwrap()
+            .store(email, user, time::now());
     }
 
-    pub fn get_cached_user(&self, email: &str) -> Option<GithubUser> {
-        match self.user_cache.get(email) {
-            Some(record) => match record {
-                GitHubUserCacheRecord::Some(user) => Some(user.clone()),
-                GitHubUserCacheRecord::None => None,
-            },
-            None => None,
-        }
+    /// Returns the cached search result of an email,
+    /// or `None` if it is not cached or has expired.
+    pub fn get_cached_user(&self, email: &str) -> Option<Option<GithubUser>> {
+        self.user_cache
+            .lock()
+            .unwrap()
+            .get(email, time::now())
     }
 
+    /// Parses the user found by the search. The search also matches other
+    /// fields than the email, so only a single match is taken as the user.
     fn parse_user_from_search_response(
         response: &str,
     ) -> Result<Option<GithubUser>, String> {
@@ -201,15 +271,16 @@ impl GitHubClient {
 
         let json = json.unwrap();
 
-        if json["total_count"].is_null() {
-            return Err("Invalid JSON response".to_string());
-        }
-        if json["total_count"].as_u32().unwrap() == 0 {
+        let total_count = match json["total_count"].as_u32() {
+            Some(total_count) => total_count,
+            None => return Err("Invalid JSON response".to_string()),
+        };
+        if total_count != 1 {
             return Ok(None);
         }
 
         let items = json["items"].clone();
-        if items.is_array() && !items.is_empty() {
+        if items.is_array() && items.len() == 1 {
             let item = &items[0];
             let username = item["login"].to_string();
             let avatar_url = item["avatar_url"].to_string();
@@ -226,141 +297,26 @@ impl GitHubClient {
     }
 }
 
-/// Implementation for summary content, since it's so long.
-impl GitHubClient {
-    pub fn create_summary_content(
-        &self,
-        summary: &analysis::CommitterCoverageSummary,
-        min_threshold: f32,
-    ) -> String {
-        let mut content = String::new();
-        let header = self.create_summary_content_header(summary, min_threshold);
-        content.push_str(header.as_str());
-
-        let table = self.create_summary_content_table(summary, min_threshold);
-        content.push_str(table.as_str());
-
-        let footer = "\n⭐ [github-action-committer-coverage-stats](https://github.com/petrabarus/github-action-committer-coverage-stats)";
-        content.push_str(footer);
-
-        content
-    }
-
-    fn create_summary_content_header(
-        &self,
-        summary: &analysis::CommitterCoverageSummary,
-        _min_threshold: f32,
-    ) -> String {
-        let mut header = String::new();
-        header.push_str("# Committer Coverage Report\n");
-        header.push_str(&format!(
-            "Total coverage: {} / {} ({:.2}%)\n\n",
-            summary.get_covered(),
-            summary.get_lines(),
-            summary.get_percent_covered()
-        ));
-        header
-    }
-
-    fn create_summary_content_table(
-        &self,
-        summary: &analysis::CommitterCoverageSummary,
-        min_threshold: f32,
-    ) -> String {
-        let mut table = String::new();
-        let table_header =
-            "|  | **User** | **Lines** | **Covered** | **% Covered** |
-|--|------|-------:|---------:|-----------|
-";
-
-        table.push_str(table_header);
-
-        let mut sorted_user_stats: Vec<CommitterCoverageUserStat> =
-            summary.get_user_stats().values().cloned().collect();
-
-        sorted_user_stats.sort_by(|a, b| {
-            let a = a.get_percent_covered();
-            let b = b.get_percent_covered();
-            b.partial_cmp(&a).unwrap()
-        });
-
-        for user_stat in sorted_user_stats {
-            let percent_covered = user_stat.get_percent_covered();
-            let status = if percent_covered >= min_threshold {
-                "✅"
-            } else {
-                "❌"
-            };
-
-            let user =
-                self.create_summary_content_table_row_user_display(&user_stat);
-
-            table.push_str(&format!(
-                "| {} | {} | {} | {:.2} {} |\n",
-                user,
-                user_stat.get_lines(),
-                user_stat.get_covered(),
-                user_stat.get_percent_covered(),
-                status
-            ));
-        }
-
-        table
-    }
-
-    fn create_summary_content_table_row_user_display(
-        &self,
-        user_stat: &CommitterCoverageUserStat,
-    ) -> String {
-        let email = user_stat.get_email();
-        let name = user_stat.get_name();
-
-        if !EmailAddress::is_valid(email) {
-            eprintln!("Invalid email: {}", email);
-            return self.create_unknown_user_display(name);
-        }
-
-        match self.get_user_by_email(email) {
-            Ok(user) => match user {
-                Some(user) => self.create_user_display(
-                    user.username.as_str(),
-                    user.url.as_str(),
-                    user.avatar_url.as_str(),
-                ),
-                None => {
-                    eprintln!("Received None user when creating summary table");
-                    self.create_unknown_user_display(name)
-                }
-            },
-            Err(err) => {
-                eprintln!("Failed to get user by email, got error when creating summary table: {}", err);
-                self.create_unknown_user_display(name)
+/// Percent-encodes a URL query component, e.g. `a+b@example.com`.
+fn encode_query_component(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
             }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
         }
     }
+    encoded
+}
 
-    fn create_unknown_user_display(&self, name: &Option<String>) -> String {
-        self.create_user_display(
-            self.must_get_name(name).as_str(),
-            "https://github.com", 
-            "https://avatars.githubusercontent.com/u/1234567890?v=4"
-        )
-    }
-
-    fn create_user_display(&self, name: &str, url: &str, avatar_url: &str) -> String {
-        format!(
-            "<a href=\"{}\"><img src=\"{}\" width=\"20\"/></a> | {}",
-            url, avatar_url, name
-        )
-    }
-
-    fn must_get_name(&self, name: &Option<String>) -> String {
-        if let Some(name) = name {
-            name.to_string()
-        } else {
-            eprintln!("Email and name are both invalid");
-            "unknown".to_string()
-        }
+impl UserProvider for GitHubClient {
+    fn get_user_by_email(
+        &self,
+        email: &str,
+    ) -> Result<Option<GithubUser>, String> {
+        GitHubClient::get_user_by_email(self, email)
     }
 }
 
@@ -372,7 +328,9 @@ pub struct GithubUser {
 }
 
 /// Parse the pull request number from the GitHub ref.
+/// Prefer the pull request of the event payload, see `event::Event`.
 /// ```
+/// use github_action_committer_coverage_stats::github;
 /// let pr_number = github::parse_pr_number_from_ref("123/merge");
 /// assert_eq!(pr_number, Some(123));
 /// ```
@@ -389,149 +347,33 @@ impl BlameProvider for GitHubClient {
         &self,
         path: &str
     ) -> Result<BlameFile, String> {
-        //eprintln!("Requesting blame for file: {}", path);
-        let json_result =
-            self.request_graphql_blame(path).map_err(|err| {
-                format!("Failed to request blame for file {}: {}", path, err)
-            })?;
-
-        let mut blame_file = BlameFile::new_from_path(path);
-        let vec = GitHubClient::parse_blame_lines_from_graphql_blame_result(
-            json_result.as_str(),
-        )
-        .map_err(|err| {
-            format!(
-                "Failed to parse blame lines from GraphQL response: {}",
-                err
-            )
-        })?;
+        self.request_files_blame(&[path.to_string()])
+            .pop()
+            .unwrap_or_else(|| Err(format!("No blame for file {}", path)))
+    }
 
-        blame_file.set_lines_from_vec(vec);
+    fn get_batch_size(&self) -> usize {
+        blame::FILES_PER_QUERY
+    }
 
-        Ok(blame_file)
+    fn get_files_blame(&self, paths: &[String]) -> Vec<Result<BlameFile, String>> {
+        self.request_files_blame(paths)
     }
 }
 
-/// Implementation for GitHubClient for the BlameProvider trait.
 impl GitHubClient {
-    // fn request_graphql_default_branch(&self) -> String {
-    //     "main".to_string()
-    // }
-
-    /// Request blame information from GitHub API using GraphQL.
-    /// This will return the response as a string.
-    /// If there is an error, it will return an error message.
-    fn request_graphql_blame(
-        &self,
-        path: &str,
-    ) -> Result<String, String> {
-        let (repo_owner, repo_name) = self.repo.split_once('/').unwrap();
-        let graphql_query = format!(
-            "
-query {{
-  repository(
-      owner:\"{}\", 
-      name:\"{}\"
-  ) {{
-      object(expression: \"{}\") {{
-        ... on Commit {{
-          blame(path: \"{}\") {{
-              ranges {{
-              startingLine,
-              endingLine,
-              commit {{
-                oid,
-                author {{
-                  name,
-                  email
-                }}
-              }}
-            }}
-          }}
-        }}
-      }}
-    }}
-}}
-",
-            repo_owner, repo_name, "main", path
-        );
+    /// Sends a GraphQL query, returns the response as a string.
+    /// The queries only read, so they are retried like a GET.
+    fn request_graphql(&self, query: &str) -> Result<String, String> {
         let mut data = json::JsonValue::new_object();
-        data["query"] = graphql_query.into();
-        let data = data.dump();
-        //eprintln!("data: {}", data);
+        data["query"] = query.into();
 
         let graphql_url = format!("{}/graphql", self.api_url);
-
-        let req = self
-            .create_sync_post_client(&graphql_url)
-            .body(data);
-        let result = req.send();
-
-        match result {
-            Ok(result) => match result.status() {
-                StatusCode::OK => {
-                    let response = result.text().map_err(|err| {
-                        format!("Failed to read response: {}", err)
-                    })?;
-                    Ok(response)
-                }
-                status => Err(format!(
-                    "Failed to send request: {}",
-                    status.canonical_reason().unwrap_or("Unknown Status")
-                )),
-            },
-            Err(err) => Err(format!("Failed to send request: {}", err)),
-        }
-    }
-
-    fn parse_blame_lines_from_graphql_blame_result(
-        response: &str,
-    ) -> Result<Vec<BlameLine>, String> {
-        let json = json::parse(response);
-        if let Err(err) = json {
-            return Err(format!("Failed to parse JSON: {}", err));
-        }
-        let json = json.unwrap();
-
-        let error = &json["data"]["errors"];
-        if !error.is_null() {
-            return Err(format!(
-                "Failed to get blame lines from GraphQL response: {}",
-                error.dump()
-            ));
-        }
-
-        // get value of data.repository.object.blame.ranges and then iterate
-        let blame_ranges =
-            &json["data"]["repository"]["object"]["blame"]["ranges"];
-        if !blame_ranges.is_array() {
-            return Err(format!("Invalid JSON response, got {}", json.dump()));
-        }
-        let blame_ranges = blame_ranges.members();
-
-        let mut vec = Vec::new();
-
-        for range in blame_ranges {
-            let starting_line = range["startingLine"].as_u32().unwrap();
-            let ending_line = range["endingLine"].as_u32().unwrap();
-            let commit = range["commit"]["oid"].as_str().unwrap();
-            let author_name =
-                range["commit"]["author"]["name"].as_str().unwrap();
-            let email = range["commit"]["author"]["email"].as_str().unwrap();
-
-            // iterate from starting_line to ending_line
-            for line_num in starting_line..=ending_line {
-                let line = BlameLine::new(
-                    line_num,
-                    commit,
-                    Some(email.to_string()),
-                    Some(author_name.to_string()),
-                );
-                //eprintln!("line: {}", line);
-                vec.push(line);
-            }
-        }
-        Ok(vec)
+        self.send_query_request(
+            self.create_sync_post_client(&graphql_url)?,
+            data,
+            StatusCode::OK,
+        )
     }
 }
 
@@ -590,6 +432,24 @@ mod tests {
         assert_eq!(user.url, "https://github.com/testuser");
     }
 
+    #[test]
+    fn test_githubclient_get_user_by_email_uses_cache() {
+        // The API URL is invalid, so any request would fail.
+        let client = GitHubClient::new("http://invalid.invalid", "a/b", "");
+        let user = GithubUser {
+            username: "user".to_string(),
+            avatar_url: "".to_string(),
+            url: "".to_string(),
+        };
+        client.store_cache_user("user@example.com", &Some(user));
+        client.store_cache_user("nobody@example.com", &None);
+
+        let user = client.get_user_by_email("user@example.com").unwrap();
+        assert_eq!("user", user.unwrap().username);
+        assert!(client.get_user_by_email("nobody@example.com").unwrap().is_none());
+        assert!(client.get_cached_user("other@example.com").is_none());
+    }
+
     #[test]
     fn test_githubclient_parse_user_from_search_response_empty() {
         let response = r#"
@@ -607,83 +467,27 @@ mod tests {
     }
 
     #[test]
-    fn test_githubclient_parse_blame_lines_from_api_graphql_blame_response_should_return_correct_value(
-    ) {
+    fn test_githubclient_parse_user_from_search_response_ambiguous() {
         let response = r#"
         {
-            "data": {
-              "repository": {
-                "object": {
-                  "blame": {
-                    "ranges": [
-                      {
-                        "startingLine": 1,
-                        "endingLine": 5,
-                        "commit": {
-                          "oid": "8d5445550b1948b914853fc7f210ff3622ee0c18",
-                          "author": {
-                            "name": "User 1",
-                            "email": "user1@example.com"
-                          }
-                        }
-                      },
-                      {
-                        "startingLine": 6,
-                        "endingLine": 6,
-                        "commit": {
-                          "oid": "5d2595a1368702ac796582016b764dedceabde85",
-                          "author": {
-                            "name": "User 2",
-                            "email": "user2@example.com"
-                          }
-                        }
-                      },
-                      {
-                        "startingLine": 7,
-                        "endingLine": 57,
-                        "commit": {
-                          "oid": "8d5445550b1948b914853fc7f210ff3622ee0c18",
-                          "author": {
-                            "name": "User 3",
-                            "email": "user3@example.com"
-                          }
-                        }
-                      }
-                    ]
-                  }
-                }
-              }
-            }
+            "total_count": 2,
+            "incomplete_results": false,
+            "items": [
+              { "login": "a", "avatar_url": "", "html_url": "https://github.com/a" },
+              { "login": "b", "avatar_url": "", "html_url": "https://github.com/b" }
+            ]
           }
         "#;
 
-        let result =
-            GitHubClient::parse_blame_lines_from_graphql_blame_result(response);
-        assert!(result.is_ok());
-        let vec = result.unwrap();
-
-        assert_eq!(57, vec.len());
-        let line_57 = &vec[56];
-        assert_eq!(
-            "8d5445550b1948b914853fc7f210ff3622ee0c18",
-            line_57.get_commit()
-        );
-        assert_eq!("user3@example.com", line_57.get_email().clone().unwrap());
+        let user = GitHubClient::parse_user_from_search_response(response);
+        assert!(user.unwrap().is_none());
     }
 
     #[test]
-    pub fn test_githubclient_parse_blame_lines_from_api_graphql_blame_response_should_return_error_when_invalid_json(
-    ) {
-        let response = r#"
-        {
-            "data": {
-
-            }
-        }
-"#;
-
-        let result =
-            GitHubClient::parse_blame_lines_from_graphql_blame_result(response);
-        assert!(result.is_err());
+    fn test_encode_query_component() {
+        assert_eq!(
+            "a%2Bb%40example.com%20in%3Aemail",
+            encode_query_component("a+b@example.com in:email")
+        );
     }
 }
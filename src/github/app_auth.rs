@@ -0,0 +1,207 @@
+//! Authenticates as a GitHub App installation instead of with a token.
+//! see: https://docs.github.com/en/apps/creating-github-apps/authenticating-with-a-github-app
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use super::GitHubClient;
+use crate::time;
+
+/// The JWT is valid for at most 10 minutes, this leaves room for clock drift.
+const JWT_EXPIRES_IN_SECS: u64 = 9 * 60;
+
+/// The JWT is issued in the past to allow for clock drift.
+const JWT_ISSUED_BEFORE_SECS: u64 = 60;
+
+/// The installation token is refreshed this long before it expires.
+const TOKEN_REFRESH_BEFORE_SECS: i64 = 5 * 60;
+
+/// The claims of the JWT, see the documentation of GitHub Apps.
+#[derive(Debug, Deserialize, Serialize)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+/// An installation token and the time it expires, in seconds since the epoch.
+#[derive(Clone)]
+struct InstallationToken {
+    token: String,
+    expires_at: i64,
+}
+
+/// The credentials of a GitHub App and its installation token.
+pub struct AppAuth {
+    app_id: String,
+    key: EncodingKey,
+    /// If not set, the installation on the repository is looked up.
+    installation_id: Option<u64>,
+    token: Mutex<Option<InstallationToken>>,
+}
+
+impl AppAuth {
+    /// Creates the credentials from the app ID and the PEM private key.
+    pub fn new(
+        app_id: &str,
+        private_key: &str,
+        installation_id: Option<u64>,
+    ) -> Result<AppAuth, String> {
+        let key = EncodingKey::from_rsa_pem(private_key.as_bytes())
+            .map_err(|err| format!("Invalid GitHub App private key: {}", err))?;
+        Ok(AppAuth {
+            app_id: app_id.to_string(),
+            key,
+            installation_id,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Creates the JWT the app authenticates with, signed with RS256.
+    pub fn create_jwt(&self, now: u64) -> Result<String, String> {
+        let claims = Claims {
+            iat: now - JWT_ISSUED_BEFORE_SECS,
+            exp: now + JWT_EXPIRES_IN_SECS,
+            iss: self.app_id.clone(),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|err| format!("Failed to sign JWT: {}", err))
+    }
+
+    /// Returns the cached installation token if it does not expire soon.
+    fn get_cached_token(&self, now: i64) -> Option<String> {
+        let token = self.token.lock().unwrap();
+        token
+            .as_ref()
+            .filter(|token| token.expires_at - TOKEN_REFRESH_BEFORE_SECS > now)
+            .map(|token| token.token.clone())
+    }
+
+    fn store_token(&self, token: InstallationToken) {
+        *self.token.lock().unwrap() = Some(token);
+    }
+}
+
+/// Parses the token and its expiry from the access tokens response.
+fn parse_installation_token(response: &str) -> Result<InstallationToken, String> {
+    let json = json::parse(response)
+        .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+    let token = json["token"]
+        .as_str()
+        .ok_or("Installation token response has no token")?;
+    let expires_at = json["expires_at"]
+        .as_str()
+        .ok_or("Installation token response has no expires_at")?;
+    Ok(InstallationToken {
+        token: token.to_string(),
+        expires_at: time::parse_datetime(expires_at)?,
+    })
+}
+
+impl GitHubClient {
+    /// Authenticates the requests as the installation of a GitHub App.
+    pub fn set_app_auth(&mut self, app_auth: AppAuth) {
+        self.app_auth = Some(app_auth);
+    }
+
+    /// Returns the token the requests are authenticated with, the installation
+    /// token if authenticated as a GitHub App, refreshed if it expires soon.
+    pub(super) fn get_token(&self) -> Result<String, String> {
+        let app_auth = match &self.app_auth {
+            Some(app_auth) => app_auth,
+            None => return Ok(self.token.clone()),
+        };
+
+        let now = time::now();
+        if let Some(token) = app_auth.get_cached_token(now as i64) {
+            return Ok(token);
+        }
+
+        let jwt = app_auth.create_jwt(now)?;
+        let installation_id = match app_auth.installation_id {
+            Some(id) => id,
+            None => self.request_repo_installation_id(&jwt)?,
+        };
+
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.api_url, installation_id
+        );
+        let request = self.add_basic_headers_to_request(self.create_sync_client().post(&url));
+        let request = self.add_bearer_token_to_request(request, &jwt);
+        let response = self
+            .send_request(request, json::JsonValue::Null, StatusCode::CREATED)
+            .map_err(|err| format!("Failed to get installation token: {}", err))?;
+
+        let token = parse_installation_token(&response)?;
+        app_auth.store_token(token.clone());
+        Ok(token.token)
+    }
+
+    /// Looks up the installation of the app on the repository.
+    fn request_repo_installation_id(&self, jwt: &str) -> Result<u64, String> {
+        let url = format!("{}/repos/{}/installation", self.api_url, self.repo);
+        let request = self.add_basic_headers_to_request(self.create_sync_client().get(&url));
+        let request = self.add_bearer_token_to_request(request, jwt);
+        let response = self
+            .send_request(request, json::JsonValue::Null, StatusCode::OK)
+            .map_err(|err| format!("Failed to get app installation: {}", err))?;
+        json::parse(&response)
+            .map_err(|err| format!("Failed to parse JSON: {}", err))?["id"]
+            .as_u64()
+            .ok_or("App installation response has no id".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+
+    // a key generated for the tests only.
+    const PRIVATE_KEY: &str = include_str!("../../res/tests/github-app-key.pem");
+    const PUBLIC_KEY: &str = include_str!("../../res/tests/github-app-key.pub.pem");
+
+    #[test]
+    fn test_app_auth_create_jwt() {
+        let app_auth = AppAuth::new("123", PRIVATE_KEY, Some(1)).unwrap();
+        let now = time::now();
+        let jwt = app_auth.create_jwt(now).unwrap();
+
+        let key = DecodingKey::from_rsa_pem(PUBLIC_KEY.as_bytes()).unwrap();
+        let validation = Validation::new(Algorithm::RS256);
+        let token = jsonwebtoken::decode::<Claims>(&jwt, &key, &validation).unwrap();
+        assert_eq!(Algorithm::RS256, token.header.alg);
+        assert_eq!("123", token.claims.iss);
+        assert_eq!(now - 60, token.claims.iat);
+        assert_eq!(now + 540, token.claims.exp);
+
+        assert!(AppAuth::new("123", "not a key", None).is_err());
+    }
+
+    #[test]
+    fn test_app_auth_cached_token() {
+        let app_auth = AppAuth::new("123", PRIVATE_KEY, None).unwrap();
+        assert!(app_auth.get_cached_token(0).is_none());
+
+        app_auth.store_token(InstallationToken {
+            token: "ghs_token".to_string(),
+            expires_at: 3600,
+        });
+        assert_eq!(Some("ghs_token".to_string()), app_auth.get_cached_token(3000));
+        // refreshed 5 minutes before it expires.
+        assert!(app_auth.get_cached_token(3300).is_none());
+    }
+
+    #[test]
+    fn test_parse_installation_token() {
+        let token = parse_installation_token(
+            r#"{ "token": "ghs_token", "expires_at": "2016-07-11T22:14:10Z" }"#,
+        )
+        .unwrap();
+        assert_eq!("ghs_token", token.token);
+        assert_eq!(1468275250, token.expires_at);
+        assert!(parse_installation_token("{}").is_err());
+    }
+}
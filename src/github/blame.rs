@@ -0,0 +1,284 @@
+//! Blames several files in one GraphQL query, each file aliased `f0`, `f1`, ...
+use json::JsonValue;
+
+use super::GitHubClient;
+use crate::{
+    git::{BlameAuthor, BlameCommit, BlameFile, BlameLine},
+    time,
+};
+use std::sync::Arc;
+
+/// The number of files blamed per GraphQL query. Blaming a large file is
+/// expensive, so a query with many files may hit the timeout of the API.
+pub const FILES_PER_QUERY: usize = 10;
+
+/// Creates a GraphQL query for the blame of each path at the expression.
+fn create_query(
+    owner: &str,
+    name: &str,
+    expression: &str,
+    paths: &[String],
+) -> String {
+    let blames: Vec<String> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            format!(
+                "f{}: blame(path: {}) {{ ranges {{ startingLine, endingLine, commit {{ oid, committedDate, author {{ name, email }} }} }} }}",
+                i,
+                json::stringify(path.as_str())
+            )
+        })
+        .collect();
+    format!(
+        "query {{ repository(owner: {}, name: {}) {{ object(expression: {}) {{ ... on Commit {{ {} }} }} }} }}",
+        json::stringify(owner),
+        json::stringify(name),
+        json::stringify(expression),
+        blames.join(" ")
+    )
+}
+
+fn parse_ranges(ranges: &JsonValue) -> Result<Vec<BlameLine>, String> {
+    if !ranges.is_array() {
+        return Err(format!("Invalid blame ranges: {}", ranges.dump()));
+    }
+
+    let mut lines = Vec::new();
+    for range in ranges.members() {
+        let (starting_line, ending_line, commit) = match (
+            range["startingLine"].as_u32(),
+            range["endingLine"].as_u32(),
+            range["commit"]["oid"].as_str(),
+        ) {
+            (Some(starting_line), Some(ending_line), Some(commit)) => {
+                (starting_line, ending_line, commit)
+            }
+            _ => return Err(format!("Invalid blame range: {}", range.dump())),
+        };
+        let author = &range["commit"]["author"];
+        let email = author["email"].as_str().map(|email| email.to_string());
+        let name = author["name"].as_str().map(|name| name.to_string());
+        let mut commit =
+            BlameCommit::new(commit, Arc::new(BlameAuthor::new(email, name)));
+        let time = range["commit"]["committedDate"].as_str().map(time::parse_datetime);
+        commit.set_time(time.transpose()?);
+        let commit = Arc::new(commit);
+        for line in starting_line..=ending_line {
+            lines.push(BlameLine::new_from_commit(line, &commit));
+        }
+    }
+    Ok(lines)
+}
+
+/// Returns the messages of the errors of the alias, e.g. a path that does not exist.
+fn find_alias_errors(errors: &JsonValue, alias: &str) -> Option<String> {
+    let messages: Vec<&str> = errors
+        .members()
+        .filter(|error| error["path"].members().any(|part| part == alias))
+        .filter_map(|error| error["message"].as_str())
+        .collect();
+    if messages.is_empty() {
+        return None;
+    }
+    Some(messages.join(", "))
+}
+
+/// Parses the blame of each path from the response, in the order of the query.
+/// A path that failed is an error on its own, the others are still parsed.
+fn parse_files_from_response(
+    response: &str,
+    paths: &[String],
+) -> Result<Vec<Result<BlameFile, String>>, String> {
+    let json = json::parse(response)
+        .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+    let object = &json["data"]["repository"]["object"];
+    if !object.is_object() {
+        return Err(format!(
+            "Failed to get blame from GraphQL response: {}",
+            json["errors"].dump()
+        ));
+    }
+
+    let files = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let alias = format!("f{}", i);
+            let blame = &object[alias.as_str()];
+            if blame.is_null() {
+                let message = find_alias_errors(&json["errors"], &alias)
+                    .unwrap_or("no blame in the response".to_string());
+                return Err(format!("Failed to blame file {}: {}", path, message));
+            }
+            let lines = parse_ranges(&blame["ranges"])
+                .map_err(|err| format!("Failed to blame file {}: {}", path, err))?;
+            let mut blame_file = BlameFile::new_from_path(path);
+            blame_file.set_lines_from_vec(lines);
+            Ok(blame_file)
+        })
+        .collect();
+    Ok(files)
+}
+
+impl GitHubClient {
+    /// Blames the files in one GraphQL query, the results are in the order
+    /// of the paths. If the whole query fails, each file gets its error.
+    pub(super) fn request_files_blame(
+        &self,
+        paths: &[String],
+    ) -> Vec<Result<BlameFile, String>> {
+        let (owner, name) = match self.repo.split_once('/') {
+            Some(parts) => parts,
+            None => {
+                let err = format!("Invalid repository: {}", self.repo);
+                return paths.iter().map(|_| Err(err.clone())).collect();
+            }
+        };
+        let query = create_query(owner, name, &self.blame_ref, paths);
+        let files = self
+            .request_graphql(&query)
+            .and_then(|response| parse_files_from_response(&response, paths));
+        match files {
+            Ok(files) => files,
+            Err(err) => paths
+                .iter()
+                .map(|path| {
+                    Err(format!("Failed to request blame for file {}: {}", path, err))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_query() {
+        let paths = vec!["src/lib.rs".to_string(), "src/\"quoted\".rs".to_string()];
+        let query = create_query("owner", "repo", "abc", &paths);
+        assert!(query.starts_with(
+            "query { repository(owner: \"owner\", name: \"repo\") { object(expression: \"abc\") { ... on Commit { f0: blame(path: \"src/lib.rs\")"
+        ));
+        assert!(query.contains("f1: blame(path: \"src/\\\"quoted\\\".rs\") { ranges {"));
+    }
+
+    #[test]
+    fn test_parse_files_from_response() {
+        let response = r#"{
+            "data": {
+                "repository": {
+                    "object": {
+                        "f0": {
+                            "ranges": [
+                                { "startingLine": 1, "endingLine": 2, "commit": { "oid": "abc", "committedDate": "2016-07-11T22:14:10Z", "author": { "name": "User", "email": "user@example.com" } } },
+                                { "startingLine": 3, "endingLine": 3, "commit": { "oid": "def", "author": { "name": "Other", "email": "other@example.com" } } }
+                            ]
+                        },
+                        "f1": null
+                    }
+                }
+            },
+            "errors": [
+                { "path": ["repository", "object", "f1"], "message": "Could not resolve file for path 'missing.rs'." }
+            ]
+        }"#;
+        let paths = vec!["src/lib.rs".to_string(), "missing.rs".to_string()];
+        let files = parse_files_from_response(response, &paths).unwrap();
+        assert_eq!(2, files.len());
+
+        let file = files[0].as_ref().unwrap();
+        assert_eq!("src/lib.rs", file.get_path());
+        assert_eq!(3, file.get_lines().len());
+        let line = file.get_lines().get(&2).unwrap();
+        assert_eq!("abc", line.get_commit());
+        assert_eq!(&Some("user@example.com".to_string()), line.get_email());
+        assert_eq!(Some(1468275250), line.get_time());
+        assert_eq!("def", file.get_lines().get(&3).unwrap().get_commit());
+
+        let err = files[1].as_ref().err().unwrap();
+        assert!(err.contains("Could not resolve file"), "{}", err);
+
+        let response = r#"{ "errors": [{ "message": "Bad credentials" }] }"#;
+        assert!(parse_files_from_response(response, &paths).is_err());
+    }
+
+    #[test]
+    fn test_parse_files_from_response_expands_ranges() {
+        let response = r#"
+        {
+            "data": {
+              "repository": {
+                "object": {
+                  "f0": {
+                    "ranges": [
+                      {
+                        "startingLine": 1,
+                        "endingLine": 5,
+                        "commit": {
+                          "oid": "8d5445550b1948b914853fc7f210ff3622ee0c18",
+                          "author": {
+                            "name": "User 1",
+                            "email": "user1@example.com"
+                          }
+                        }
+                      },
+                      {
+                        "startingLine": 6,
+                        "endingLine": 6,
+                        "commit": {
+                          "oid": "5d2595a1368702ac796582016b764dedceabde85",
+                          "author": {
+                            "name": "User 2",
+                            "email": "user2@example.com"
+                          }
+                        }
+                      },
+                      {
+                        "startingLine": 7,
+                        "endingLine": 57,
+                        "commit": {
+                          "oid": "8d5445550b1948b914853fc7f210ff3622ee0c18",
+                          "author": {
+                            "name": "User 3",
+                            "email": "user3@example.com"
+                          }
+                        }
+                      }
+                    ]
+                  }
+                }
+              }
+            }
+          }
+        "#;
+
+        let paths = vec!["src/lib.rs".to_string()];
+        let mut files = parse_files_from_response(response, &paths).unwrap();
+        let file = files.pop().unwrap().unwrap();
+
+        assert_eq!(57, file.get_lines().len());
+        let line_57 = file.get_lines().get(&57).unwrap();
+        assert_eq!(
+            "8d5445550b1948b914853fc7f210ff3622ee0c18",
+            line_57.get_commit()
+        );
+        assert_eq!("user3@example.com", line_57.get_email().clone().unwrap());
+    }
+
+    #[test]
+    fn test_parse_files_from_response_invalid_json() {
+        let response = r#"
+        {
+            "data": {
+
+            }
+        }
+"#;
+
+        let paths = vec!["src/lib.rs".to_string()];
+        assert!(parse_files_from_response(response, &paths).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Creates a GitHub Check Run with annotations on the uncovered lines
+//! that are attributed to the commits of a pull request.
+use json::{object, JsonValue};
+use reqwest::StatusCode;
+use std::collections::HashSet;
+
+use super::GitHubClient;
+use crate::analysis::{CommitterCoverageSummary, ThresholdRules};
+
+/// The maximum number of annotations GitHub accepts per request.
+pub const MAX_ANNOTATIONS_PER_REQUEST: usize = 50;
+
+/// The maximum number of commits GitHub lists for a pull request.
+const MAX_PULL_REQUEST_COMMITS: usize = 250;
+
+/// A warning on a range of uncovered lines in a file.
+#[derive(Debug, PartialEq)]
+pub struct Annotation {
+    path: String,
+    start_line: u32,
+    end_line: u32,
+    commit: String,
+    email: String,
+}
+
+impl Annotation {
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_start_line(&self) -> u32 {
+        self.start_line
+    }
+
+    pub fn get_end_line(&self) -> u32 {
+        self.end_line
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let title = if self.start_line == self.end_line {
+            "Uncovered line".to_string()
+        } else {
+            format!(
+                "Uncovered lines {}-{}",
+                self.start_line, self.end_line
+            )
+        };
+        let short_commit: String = self.commit.chars().take(7).collect();
+        object! {
+            "path" => self.path.as_str(),
+            "start_line" => self.start_line,
+            "end_line" => self.end_line,
+            "annotation_level" => "warning",
+            "title" => title,
+            "message" => format!(
+                "Not covered by tests, added by {} in {}.",
+                self.email, short_commit
+            ),
+        }
+    }
+}
+
+/// Creates the annotations for the uncovered lines blamed to one of the
+/// given commits. Consecutive lines of the same commit are merged.
+pub fn create_annotations(
+    summary: &CommitterCoverageSummary,
+    commits: &HashSet<String>,
+) -> Vec<Annotation> {
+    let mut annotations: Vec<Annotation> = Vec::new();
+
+    for (path, lines) in summary.get_file_lines() {
+        let mut lines: Vec<_> = lines
+            .iter()
+            .filter(|line| {
+                !line.is_covered() && commits.contains(line.get_commit())
+            })
+            .collect();
+        lines.sort_by_key(|line| line.get_line());
+
+        for line in lines {
+            if let Some(last) = annotations.last_mut() {
+                if last.path == *path
+                    && last.commit == line.get_commit()
+                    && last.end_line + 1 == line.get_line()
+                {
+                    last.end_line = line.get_line();
+                    continue;
+                }
+            }
+            annotations.push(Annotation {
+                path: path.to_string(),
+                start_line: line.get_line(),
+                end_line: line.get_line(),
+                commit: line.get_commit().to_string(),
+                email: line.get_email().to_string(),
+            });
+        }
+    }
+
+    annotations
+}
+
+/// Returns the conclusion of the check run from the thresholds.
+/// Failing committers only fail the check if `fail_below_threshold` is set,
+/// otherwise the check is neutral.
+pub fn create_conclusion(
+    summary: &CommitterCoverageSummary,
+    rules: &ThresholdRules,
+    fail_below_threshold: bool,
+) -> &'static str {
+    let failing = summary
+        .get_user_stats()
+        .values()
+        .any(|user_stat| !rules.evaluate(user_stat).is_passed());
+    match (failing, fail_below_threshold) {
+        (false, _) => "success",
+        (true, true) => "failure",
+        (true, false) => "neutral",
+    }
+}
+
+/// Returns the title and the summary of the check run output.
+/// Committers below the minimum lines are not counted, like the commit status.
+pub fn create_output_text(
+    summary: &CommitterCoverageSummary,
+    rules: &ThresholdRules,
+    annotations: usize,
+) -> (String, String) {
+    let decisions: Vec<_> = summary
+        .get_user_stats()
+        .values()
+        .map(|user_stat| rules.evaluate(user_stat))
+        .filter(|decision| !decision.is_skipped())
+        .collect();
+    let passed = decisions.iter().filter(|d| d.is_passed()).count();
+    let title = format!(
+        "{:.2}% covered, {} of {} committers pass",
+        summary.get_percent_covered(),
+        passed,
+        decisions.len()
+    );
+    let text = format!(
+        "{} of {} lines are covered by tests. {} uncovered range(s) are \
+         attributed to the commits of this pull request.",
+        summary.get_covered(),
+        summary.get_lines(),
+        annotations
+    );
+    (title, text)
+}
+
+impl GitHubClient {
+    /// Creates a completed check run on the head of the pull request.
+    /// The annotations are sent in batches, the first one with the creation
+    /// and the rest as updates of the check run.
+    pub fn create_check_run(
+        &self,
+        pull_request_number: u32,
+        name: &str,
+        summary: &CommitterCoverageSummary,
+        rules: &ThresholdRules,
+        fail_below_threshold: bool,
+    ) -> Result<(), String> {
+        let head_sha = self.get_pull_request_head_sha(pull_request_number)?;
+        let commits = self.request_pull_request_commits(pull_request_number)?;
+        let annotations = create_annotations(summary, &commits);
+        let (title, text) =
+            create_output_text(summary, rules, annotations.len());
+        let conclusion =
+            create_conclusion(summary, rules, fail_below_threshold);
+
+        let mut batches = annotations.chunks(MAX_ANNOTATIONS_PER_REQUEST);
+        let first = batches.next().unwrap_or(&[]);
+
+        let data = object! {
+            "name" => name,
+            "head_sha" => head_sha,
+            "status" => "completed",
+            "conclusion" => conclusion,
+            "output" => create_output(&title, &text, first),
+        };
+        let url = format!("{}/repos/{}/check-runs", self.api_url, self.repo);
+        let response = self.send_request(
+            self.create_sync_post_client(&url)?,
+            data,
+            StatusCode::CREATED,
+        )?;
+        let id = json::parse(&response)
+            .map_err(|err| format!("Failed to parse JSON: {}", err))?["id"]
+            .as_u64()
+            .ok_or("Check run response has no id")?;
+
+        let url = format!("{}/repos/{}/check-runs/{}", self.api_url, self.repo, id);
+        for batch in batches {
+            let data = object! {
+                "output" => create_output(&title, &text, batch),
+            };
+            self.send_request(
+                self.create_sync_patch_client(&url)?,
+                data,
+                StatusCode::OK,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the commits of the pull request, GitHub lists at most 250.
+    fn request_pull_request_commits(
+        &self,
+        pull_request_number: u32,
+    ) -> Result<HashSet<String>, String> {
+        let per_page = 100;
+        let mut commits = HashSet::new();
+        for page in 1.. {
+            let url = format!(
+                "{}/repos/{}/pulls/{}/commits?per_page={}&page={}",
+                self.api_url, self.repo, pull_request_number, per_page, page
+            );
+            let response = self.send_request(
+                self.create_sync_get_client(&url)?,
+                JsonValue::Null,
+                StatusCode::OK,
+            )?;
+            let count = parse_commit_shas(&response, &mut commits)?;
+            if count < per_page || commits.len() >= MAX_PULL_REQUEST_COMMITS {
+                break;
+            }
+        }
+        Ok(commits)
+    }
+}
+
+fn create_output(title: &str, text: &str, annotations: &[Annotation]) -> JsonValue {
+    let annotations: Vec<JsonValue> =
+        annotations.iter().map(|a| a.to_json()).collect();
+    object! {
+        "title" => title,
+        "summary" => text,
+        "annotations" => annotations,
+    }
+}
+
+/// Adds the shas of a list commits response, returns the number of commits.
+fn parse_commit_shas(
+    response: &str,
+    commits: &mut HashSet<String>,
+) -> Result<usize, String> {
+    let json = json::parse(response)
+        .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+    if !json.is_array() {
+        return Err(format!("Invalid JSON response, got {}", json.dump()));
+    }
+    for commit in json.members() {
+        if let Some(sha) = commit["sha"].as_str() {
+            commits.insert(sha.to_string());
+        }
+    }
+    Ok(json.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::CommitterCoverageUserStat;
+
+    fn create_summary() -> CommitterCoverageSummary {
+        let mut summary = CommitterCoverageSummary::default();
+        let lines = [
+            (1, "pr", false),
+            (2, "pr", false),
+            (3, "pr", true),
+            (4, "pr", false),
+            (5, "old", false),
+            (6, "pr", false),
+        ];
+        for (line, commit, covered) in lines {
+            summary.create_user_stat_if_not_exists("user@example.com", None);
+            summary.incr_user_line_cover("user@example.com", "src/lib.rs", covered);
+            summary.add_attributed_line(
+                "src/lib.rs",
+                line,
+                commit,
+                "user@example.com",
+                covered,
+            );
+        }
+        summary
+    }
+
+    #[test]
+    fn test_create_annotations() {
+        let summary = create_summary();
+        let commits: HashSet<String> = ["pr".to_string()].into();
+
+        let annotations = create_annotations(&summary, &commits);
+        let ranges: Vec<(u32, u32)> = annotations
+            .iter()
+            .map(|a| (a.get_start_line(), a.get_end_line()))
+            .collect();
+        assert_eq!(vec![(1, 2), (4, 4), (6, 6)], ranges);
+        assert_eq!("src/lib.rs", annotations[0].get_path());
+
+        let json = annotations[0].to_json();
+        assert_eq!("Uncovered lines 1-2", json["title"]);
+        assert_eq!("warning", json["annotation_level"]);
+        assert_eq!(
+            "Not covered by tests, added by user@example.com in pr.",
+            json["message"]
+        );
+    }
+
+    #[test]
+    fn test_create_conclusion() {
+        let summary = create_summary();
+        assert_eq!(
+            "success",
+            create_conclusion(&summary, &ThresholdRules::new(10.0), true)
+        );
+        assert_eq!(
+            "failure",
+            create_conclusion(&summary, &ThresholdRules::new(80.0), true)
+        );
+        assert_eq!(
+            "neutral",
+            create_conclusion(&summary, &ThresholdRules::new(80.0), false)
+        );
+    }
+
+    #[test]
+    fn test_create_output_text() {
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("a@example.com", None, 100, 90),
+            CommitterCoverageUserStat::new("b@example.com", None, 100, 50),
+            CommitterCoverageUserStat::new("c@example.com", None, 2, 0),
+        ]);
+        let mut rules = ThresholdRules::new(80.0);
+        rules.set_min_lines(10);
+
+        let (title, text) = create_output_text(&summary, &rules, 3);
+        assert_eq!("69.31% covered, 1 of 2 committers pass", title);
+        assert!(text.starts_with("140 of 202 lines are covered"), "{}", text);
+    }
+
+    #[test]
+    fn test_create_output_batches() {
+        let annotations: Vec<Annotation> = (1..=120)
+            .map(|line| Annotation {
+                path: "src/lib.rs".to_string(),
+                start_line: line,
+                end_line: line,
+                commit: "pr".to_string(),
+                email: "user@example.com".to_string(),
+            })
+            .collect();
+        let outputs: Vec<JsonValue> = annotations
+            .chunks(MAX_ANNOTATIONS_PER_REQUEST)
+            .map(|batch| create_output("title", "text", batch))
+            .collect();
+        assert_eq!(3, outputs.len());
+        assert_eq!(50, outputs[0]["annotations"].len());
+        assert_eq!(20, outputs[2]["annotations"].len());
+    }
+
+    #[test]
+    fn test_parse_commit_shas() {
+        let mut commits = HashSet::new();
+        let count =
+            parse_commit_shas(r#"[{"sha": "a"}, {"sha": "b"}]"#, &mut commits)
+                .unwrap();
+        assert_eq!(2, count);
+        assert!(commits.contains("a"));
+        assert!(parse_commit_shas("{}", &mut commits).is_err());
+    }
+}
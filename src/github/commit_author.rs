@@ -0,0 +1,182 @@
+//! Resolves the GitHub users of the committers from the authors of their
+//! commits, instead of searching their emails.
+use json::JsonValue;
+use std::collections::BTreeMap;
+
+use super::{GitHubClient, GithubUser};
+
+/// The number of commits looked up per GraphQL query.
+const COMMITS_PER_QUERY: usize = 50;
+
+const NOREPLY_DOMAIN: &str = "@users.noreply.github.com";
+
+/// Parses a GitHub noreply address, `ID+login@users.noreply.github.com`
+/// or the older `login@users.noreply.github.com`, without any request.
+pub fn parse_noreply_email(email: &str) -> Option<GithubUser> {
+    let local = email.strip_suffix(NOREPLY_DOMAIN)?;
+    let (id, login) = match local.split_once('+') {
+        Some((id, login)) => (id.parse::<u64>().ok(), login),
+        None => (None, local),
+    };
+    if login.is_empty() {
+        return None;
+    }
+
+    let avatar_url = match id {
+        Some(id) => format!("https://avatars.githubusercontent.com/u/{}?v=4", id),
+        None => format!("https://github.com/{}.png", login),
+    };
+    Some(GithubUser {
+        username: login.to_string(),
+        avatar_url,
+        url: format!("https://github.com/{}", login),
+    })
+}
+
+/// Creates a GraphQL query for the author of each commit, aliased `c0`, `c1`, ...
+fn create_query(owner: &str, name: &str, commits: &[&str]) -> String {
+    let objects: Vec<String> = commits
+        .iter()
+        .enumerate()
+        .map(|(i, oid)| {
+            format!(
+                "c{}: object(oid: {}) {{ ... on Commit {{ author {{ user {{ login, avatarUrl, url }} }} }} }}",
+                i,
+                json::stringify(*oid)
+            )
+        })
+        .collect();
+    format!(
+        "query {{ repository(owner: {}, name: {}) {{ {} }} }}",
+        json::stringify(owner),
+        json::stringify(name),
+        objects.join(" ")
+    )
+}
+
+/// Parses the users of the commits from the response, in the order of the query.
+/// A commit without a linked account has no user.
+fn parse_users_from_response(
+    response: &str,
+    count: usize,
+) -> Result<Vec<Option<GithubUser>>, String> {
+    let json = json::parse(response)
+        .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+    let repository = &json["data"]["repository"];
+    if !repository.is_object() {
+        return Err(format!(
+            "Failed to get commit authors from GraphQL response: {}",
+            json["errors"].dump()
+        ));
+    }
+
+    let users = (0..count)
+        .map(|i| {
+            let user = &repository[format!("c{}", i).as_str()]["author"]["user"];
+            parse_user(user)
+        })
+        .collect();
+    Ok(users)
+}
+
+fn parse_user(user: &JsonValue) -> Option<GithubUser> {
+    let login = user["login"].as_str()?;
+    Some(GithubUser {
+        username: login.to_string(),
+        avatar_url: user["avatarUrl"].to_string(),
+        url: user["url"].to_string(),
+    })
+}
+
+impl GitHubClient {
+    /// Resolves the GitHub users of the emails from the authors of the
+    /// given commits, mapped by email, and stores them in the user cache.
+    /// Noreply addresses are parsed without any request. An email whose
+    /// commit has no linked account is not cached, `get_user_by_email`
+    /// still searches it.
+    pub fn resolve_users_by_commits(
+        &self,
+        commits_by_email: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        let mut unresolved: Vec<(&str, &str)> = Vec::new();
+        for (email, commit) in commits_by_email {
+            if self.get_cached_user(email).is_some() {
+                continue;
+            }
+            match parse_noreply_email(email) {
+                Some(user) => self.store_cache_user(email, &Some(user)),
+                None => unresolved.push((email, commit)),
+            }
+        }
+
+        let (owner, name) = self
+            .repo
+            .split_once('/')
+            .ok_or(format!("Invalid repository: {}", self.repo))?;
+        for batch in unresolved.chunks(COMMITS_PER_QUERY) {
+            let commits: Vec<&str> =
+                batch.iter().map(|(_, commit)| *commit).collect();
+            let query = create_query(owner, name, &commits);
+            let response = self.request_graphql(&query)?;
+            let users = parse_users_from_response(&response, batch.len())?;
+            for ((email, _), user) in batch.iter().zip(users) {
+                if user.is_some() {
+                    self.store_cache_user(email, &user);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_noreply_email() {
+        let user =
+            parse_noreply_email("12345+octocat@users.noreply.github.com").unwrap();
+        assert_eq!("octocat", user.username);
+        assert_eq!("https://avatars.githubusercontent.com/u/12345?v=4", user.avatar_url);
+        assert_eq!("https://github.com/octocat", user.url);
+
+        let user = parse_noreply_email("octocat@users.noreply.github.com").unwrap();
+        assert_eq!("octocat", user.username);
+        assert_eq!("https://github.com/octocat.png", user.avatar_url);
+
+        assert!(parse_noreply_email("octocat@example.com").is_none());
+        assert!(parse_noreply_email("@users.noreply.github.com").is_none());
+    }
+
+    #[test]
+    fn test_create_query() {
+        let query = create_query("owner", "repo", &["abc", "def"]);
+        assert!(query.starts_with("query { repository(owner: \"owner\", name: \"repo\") { c0: object(oid: \"abc\")"));
+        assert!(query.contains("c1: object(oid: \"def\") { ... on Commit { author { user { login, avatarUrl, url } } } }"));
+
+        // the values are string literals, they cannot end the query early.
+        let query = create_query("owner", "repo", &["abc\") } } x: viewer { login"]);
+        assert!(query.contains("c0: object(oid: \"abc\\\") } } x: viewer { login\")"), "{}", query);
+    }
+
+    #[test]
+    fn test_parse_users_from_response() {
+        let response = r#"{
+            "data": {
+                "repository": {
+                    "c0": { "author": { "user": { "login": "octocat", "avatarUrl": "https://example.com/a.png", "url": "https://github.com/octocat" } } },
+                    "c1": { "author": { "user": null } },
+                    "c2": null
+                }
+            }
+        }"#;
+        let users = parse_users_from_response(response, 3).unwrap();
+        assert_eq!("octocat", users[0].as_ref().unwrap().username);
+        assert!(users[1].is_none());
+        assert!(users[2].is_none());
+
+        let response = r#"{ "errors": [{ "message": "Bad credentials" }] }"#;
+        assert!(parse_users_from_response(response, 1).is_err());
+    }
+}
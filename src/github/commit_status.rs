@@ -0,0 +1,108 @@
+//! Posts the committer coverage as a commit status, so branch protection
+//! can require it.
+use json::object;
+use reqwest::StatusCode;
+
+use super::GitHubClient;
+use crate::analysis::{CommitterCoverageSummary, ThresholdRules};
+
+/// GitHub truncates longer descriptions.
+const MAX_DESCRIPTION_LENGTH: usize = 140;
+
+/// Returns the state of the commit status, `failure` if any committer is
+/// below their threshold.
+pub fn create_state(
+    summary: &CommitterCoverageSummary,
+    rules: &ThresholdRules,
+) -> &'static str {
+    let failing = summary
+        .get_user_stats()
+        .values()
+        .any(|user_stat| !rules.evaluate(user_stat).is_passed());
+    if failing {
+        "failure"
+    } else {
+        "success"
+    }
+}
+
+/// Returns the description of the commit status,
+/// e.g. `Committers ≥80%: 7/9, total 84.2%`.
+/// Committers below the minimum lines are not counted.
+pub fn create_description(
+    summary: &CommitterCoverageSummary,
+    rules: &ThresholdRules,
+) -> String {
+    let decisions: Vec<_> = summary
+        .get_user_stats()
+        .values()
+        .map(|user_stat| rules.evaluate(user_stat))
+        .filter(|decision| !decision.is_skipped())
+        .collect();
+    let passed = decisions.iter().filter(|d| d.is_passed()).count();
+    let description = format!(
+        "Committers ≥{}%: {}/{}, total {:.1}%",
+        rules.get_default(),
+        passed,
+        decisions.len(),
+        summary.get_percent_covered()
+    );
+    description.chars().take(MAX_DESCRIPTION_LENGTH).collect()
+}
+
+impl GitHubClient {
+    /// Creates a commit status on the given commit.
+    pub fn create_commit_status(
+        &self,
+        sha: &str,
+        context: &str,
+        summary: &CommitterCoverageSummary,
+        rules: &ThresholdRules,
+        target_url: &str,
+    ) -> Result<(), String> {
+        let mut data = object! {
+            "state" => create_state(summary, rules),
+            "description" => create_description(summary, rules),
+            "context" => context,
+        };
+        if !target_url.is_empty() {
+            data["target_url"] = target_url.into();
+        }
+
+        let url = format!("{}/repos/{}/statuses/{}", self.api_url, self.repo, sha);
+        self.send_request(
+            self.create_sync_post_client(&url)?,
+            data,
+            StatusCode::CREATED,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::CommitterCoverageUserStat;
+
+    #[test]
+    fn test_create_description_and_state() {
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("a@example.com", None, 100, 90),
+            CommitterCoverageUserStat::new("b@example.com", None, 100, 50),
+            CommitterCoverageUserStat::new("c@example.com", None, 2, 0),
+        ]);
+        let mut rules = ThresholdRules::new(80.0);
+        rules.set_min_lines(10);
+
+        assert_eq!(
+            "Committers ≥80%: 1/2, total 69.3%",
+            create_description(&summary, &rules)
+        );
+        assert_eq!("failure", create_state(&summary, &rules));
+
+        let rules = ThresholdRules::new(40.0);
+        assert_eq!("failure", create_state(&summary, &rules));
+        let rules = ThresholdRules::new(0.0);
+        assert_eq!("success", create_state(&summary, &rules));
+    }
+}
@@ -0,0 +1,274 @@
+//! Parses the webhook payload of the event that triggered the workflow.
+//! see: https://docs.github.com/en/actions/using-workflows/events-that-trigger-workflows
+use json::JsonValue;
+use std::fs;
+
+use super::parse_pr_number_from_ref;
+
+/// The pull request of an event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PullRequest {
+    number: u32,
+    author: Option<String>,
+    fork: bool,
+    draft: bool,
+}
+
+impl PullRequest {
+    pub fn new(number: u32) -> PullRequest {
+        PullRequest {
+            number,
+            author: None,
+            fork: false,
+            draft: false,
+        }
+    }
+
+    pub fn get_number(&self) -> u32 {
+        self.number
+    }
+
+    /// The login of the user who opened the pull request.
+    pub fn get_author(&self) -> &Option<String> {
+        &self.author
+    }
+
+    /// Whether the head branch is in another repository, so that the
+    /// token of a `pull_request` workflow is read-only.
+    pub fn is_fork(&self) -> bool {
+        self.fork
+    }
+
+    pub fn is_draft(&self) -> bool {
+        self.draft
+    }
+}
+
+/// The event that triggered the workflow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    name: String,
+    pull_request: Option<PullRequest>,
+    head_sha: Option<String>,
+    base_sha: Option<String>,
+}
+
+impl Event {
+    /// Parses the webhook payload of the event with the given name.
+    /// Events without a pull request are parsed without one.
+    pub fn parse(name: &str, payload: &JsonValue) -> Result<Event, String> {
+        let mut event = Event {
+            name: name.to_string(),
+            pull_request: None,
+            head_sha: None,
+            base_sha: None,
+        };
+
+        match name {
+            "pull_request" | "pull_request_target" => {
+                let pr = &payload["pull_request"];
+                let number = pr["number"]
+                    .as_u32()
+                    .ok_or(format!("The {} event has no pull request number", name))?;
+                let head_repo = pr["head"]["repo"]["full_name"].as_str();
+                let base_repo = pr["base"]["repo"]["full_name"].as_str();
+                event.pull_request = Some(PullRequest {
+                    number,
+                    author: as_string(&pr["user"]["login"]),
+                    // a deleted fork has no head repository.
+                    fork: head_repo.is_none() || head_repo != base_repo,
+                    draft: pr["draft"].as_bool().unwrap_or(false),
+                });
+                event.head_sha = as_string(&pr["head"]["sha"]);
+                event.base_sha = as_string(&pr["base"]["sha"]);
+            }
+            "merge_group" => {
+                let group = &payload["merge_group"];
+                // e.g. refs/heads/gh-readonly-queue/main/pr-123-<sha>
+                event.pull_request = group["head_ref"]
+                    .as_str()
+                    .and_then(parse_pr_number_from_merge_group_ref)
+                    .map(PullRequest::new);
+                event.head_sha = as_string(&group["head_sha"]);
+                event.base_sha = as_string(&group["base_sha"]);
+            }
+            "issue_comment" => {
+                // only comments on pull requests, not on issues.
+                let issue = &payload["issue"];
+                if !issue["pull_request"].is_null() {
+                    let number = issue["number"]
+                        .as_u32()
+                        .ok_or("The issue_comment event has no issue number")?;
+                    event.pull_request = Some(PullRequest {
+                        number,
+                        author: as_string(&issue["user"]["login"]),
+                        fork: false,
+                        draft: issue["draft"].as_bool().unwrap_or(false),
+                    });
+                }
+            }
+            "workflow_run" => {
+                event.head_sha = as_string(&payload["workflow_run"]["head_sha"]);
+            }
+            _ => {
+                event.head_sha = as_string(&payload["after"]);
+            }
+        }
+
+        Ok(event)
+    }
+
+    /// Loads the webhook payload from `GITHUB_EVENT_PATH`.
+    pub fn load_from_file(name: &str, path: &str) -> Result<Event, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read event {}: {}", path, err))?;
+        let payload = json::parse(&content)
+            .map_err(|err| format!("Failed to parse event {}: {}", path, err))?;
+        Event::parse(name, &payload)
+    }
+
+    /// Creates the event from the ref, e.g. `123/merge`, if there is no payload.
+    pub fn from_ref(name: &str, github_ref_name: &str) -> Event {
+        // the ref of pull_request_target is the base branch.
+        let pull_request = match name {
+            "pull_request" => {
+                parse_pr_number_from_ref(github_ref_name).map(PullRequest::new)
+            }
+            _ => None,
+        };
+        Event {
+            name: name.to_string(),
+            pull_request,
+            head_sha: None,
+            base_sha: None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_pull_request(&self) -> &Option<PullRequest> {
+        &self.pull_request
+    }
+
+    /// The head commit of the pull request, or of the merge group or push.
+    pub fn get_head_sha(&self) -> &Option<String> {
+        &self.head_sha
+    }
+
+    pub fn get_base_sha(&self) -> &Option<String> {
+        &self.base_sha
+    }
+
+    /// Returns the pull request to post the summary to.
+    /// A merge group is only checked, the pull request was already reviewed.
+    pub fn get_pull_request_to_publish(&self) -> Option<&PullRequest> {
+        if self.name == "merge_group" {
+            return None;
+        }
+        self.pull_request.as_ref()
+    }
+}
+
+fn as_string(value: &JsonValue) -> Option<String> {
+    value.as_str().map(|value| value.to_string())
+}
+
+/// Parses the pull request number from a merge queue branch,
+/// e.g. `refs/heads/gh-readonly-queue/main/pr-123-<sha>`.
+fn parse_pr_number_from_merge_group_ref(head_ref: &str) -> Option<u32> {
+    let (_, branch) = head_ref.rsplit_once('/')?;
+    let number = branch.strip_prefix("pr-")?.split('-').next()?;
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    fn create_pull_request_payload(head_repo: &str) -> JsonValue {
+        object! {
+            "pull_request" => object! {
+                "number" => 12,
+                "draft" => true,
+                "user" => object! { "login" => "octocat" },
+                "head" => object! {
+                    "sha" => "head",
+                    "repo" => object! { "full_name" => head_repo },
+                },
+                "base" => object! {
+                    "sha" => "base",
+                    "repo" => object! { "full_name" => "owner/repo" },
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_event_parse_pull_request() {
+        let payload = create_pull_request_payload("owner/repo");
+        let event = Event::parse("pull_request", &payload).unwrap();
+        let pr = event.get_pull_request().clone().unwrap();
+        assert_eq!(12, pr.get_number());
+        assert_eq!(&Some("octocat".to_string()), pr.get_author());
+        assert!(!pr.is_fork());
+        assert!(pr.is_draft());
+        assert_eq!(&Some("head".to_string()), event.get_head_sha());
+        assert_eq!(&Some("base".to_string()), event.get_base_sha());
+        assert!(event.get_pull_request_to_publish().is_some());
+
+        let payload = create_pull_request_payload("fork/repo");
+        let event = Event::parse("pull_request_target", &payload).unwrap();
+        assert!(event.get_pull_request().clone().unwrap().is_fork());
+
+        assert!(Event::parse("pull_request", &object! {}).is_err());
+    }
+
+    #[test]
+    fn test_event_parse_merge_group() {
+        let payload = object! {
+            "merge_group" => object! {
+                "head_sha" => "head",
+                "base_sha" => "base",
+                "head_ref" => "refs/heads/gh-readonly-queue/main/pr-34-abcdef",
+            },
+        };
+        let event = Event::parse("merge_group", &payload).unwrap();
+        assert_eq!(34, event.get_pull_request().clone().unwrap().get_number());
+        assert_eq!(&Some("head".to_string()), event.get_head_sha());
+        assert!(event.get_pull_request_to_publish().is_none());
+    }
+
+    #[test]
+    fn test_event_parse_issue_comment() {
+        let payload = object! {
+            "issue" => object! {
+                "number" => 56,
+                "pull_request" => object! { "url" => "https://example.com" },
+                "user" => object! { "login" => "octocat" },
+            },
+        };
+        let event = Event::parse("issue_comment", &payload).unwrap();
+        assert_eq!(56, event.get_pull_request().clone().unwrap().get_number());
+        assert_eq!(&None, event.get_head_sha());
+
+        let payload = object! { "issue" => object! { "number" => 56 } };
+        let event = Event::parse("issue_comment", &payload).unwrap();
+        assert!(event.get_pull_request().is_none());
+    }
+
+    #[test]
+    fn test_event_parse_others() {
+        let payload = object! { "after" => "sha" };
+        let event = Event::parse("push", &payload).unwrap();
+        assert!(event.get_pull_request().is_none());
+        assert_eq!(&Some("sha".to_string()), event.get_head_sha());
+
+        let event = Event::from_ref("pull_request", "78/merge");
+        assert_eq!(78, event.get_pull_request().clone().unwrap().get_number());
+        assert!(Event::from_ref("pull_request_target", "main").get_pull_request().is_none());
+        assert!(Event::from_ref("push", "main").get_pull_request().is_none());
+    }
+}
@@ -0,0 +1,321 @@
+//! Sends the requests of the GitHub client, retrying on rate limits,
+//! and on server errors and network errors if the request can be repeated.
+use json::JsonValue;
+use reqwest::{blocking::RequestBuilder, header::HeaderMap, Method, StatusCode};
+use std::{thread, time::Duration};
+
+use super::GitHubClient;
+use crate::time;
+
+/// The number of times a request is sent at most.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// The delay before the first retry, doubled for each further retry.
+const BACKOFF_BASE_SECS: u64 = 1;
+
+/// Waiting longer than this for a rate limit to reset fails instead.
+const MAX_RATE_LIMIT_WAIT_SECS: u64 = 300;
+
+/// The maximum length of the response body in error messages.
+const MAX_ERROR_BODY_LENGTH: usize = 500;
+
+/// What to do after a failed attempt.
+#[derive(Debug, PartialEq)]
+pub enum Retry {
+    /// Send the request again after the delay.
+    After(Duration),
+    /// Give up, e.g. on a missing permission or after the last attempt.
+    No,
+}
+
+/// Returns the delay before retrying a response with the given status,
+/// `None` for a network error. `attempt` starts at 0, `now` is in seconds
+/// since the epoch.
+pub fn retry_after(
+    status: Option<StatusCode>,
+    headers: Option<&HeaderMap>,
+    attempt: u32,
+    now: u64,
+) -> Retry {
+    if attempt + 1 >= MAX_ATTEMPTS {
+        return Retry::No;
+    }
+    let backoff = Retry::After(Duration::from_secs(BACKOFF_BASE_SECS << attempt));
+
+    let status = match status {
+        Some(status) => status,
+        None => return backoff,
+    };
+    if status.is_server_error() {
+        return backoff;
+    }
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return Retry::No;
+    }
+
+    // 403 is a rate limit only if the headers say so,
+    // otherwise it is e.g. a missing permission.
+    let wait = headers.and_then(|headers| rate_limit_wait_secs(headers, now));
+    match (wait, status) {
+        (Some(wait), _) if wait <= MAX_RATE_LIMIT_WAIT_SECS => {
+            Retry::After(Duration::from_secs(wait))
+        }
+        (Some(_), _) => Retry::No,
+        (None, StatusCode::TOO_MANY_REQUESTS) => backoff,
+        (None, _) => Retry::No,
+    }
+}
+
+/// Same as `retry_after`, but a request that is not idempotent, e.g. a POST
+/// creating a comment, is only retried on a rate limit. After a server error
+/// or a network error it may have been done already, and a retry would do it
+/// twice.
+pub fn retry_after_request(
+    idempotent: bool,
+    status: Option<StatusCode>,
+    headers: Option<&HeaderMap>,
+    attempt: u32,
+    now: u64,
+) -> Retry {
+    let done = match status {
+        Some(status) => status.is_server_error(),
+        None => true,
+    };
+    if !idempotent && done {
+        return Retry::No;
+    }
+    retry_after(status, headers, attempt, now)
+}
+
+/// Returns the seconds until the rate limit resets, from `Retry-After`,
+/// or from `X-RateLimit-Reset` if no requests are remaining.
+fn rate_limit_wait_secs(headers: &HeaderMap, now: u64) -> Option<u64> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+
+    if let Some(retry_after) = header("retry-after") {
+        return Some(retry_after);
+    }
+    if header("x-ratelimit-remaining") == Some(0) {
+        // One more second, the reset time is rounded down.
+        return header("x-ratelimit-reset")
+            .map(|reset| reset.saturating_sub(now) + 1);
+    }
+    None
+}
+
+/// Formats the error of an unexpected response, including the body
+/// so that e.g. a missing permission can be told from a rate limit.
+pub fn format_status_error(status: StatusCode, body: &str) -> String {
+    let body = body.trim();
+    let mut message = format!(
+        "Failed to send request: {} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Unknown Status")
+    );
+    if !body.is_empty() {
+        let truncated: String = body.chars().take(MAX_ERROR_BODY_LENGTH).collect();
+        message.push_str(&format!(": {}", truncated));
+        if truncated.len() < body.len() {
+            message.push_str("...");
+        }
+    }
+    message
+}
+
+impl GitHubClient {
+    /// Sends the request with the JSON data, if any, and returns the
+    /// response body if the response has the expected status.
+    /// Rate limits are retried, server errors and network errors only
+    /// if the request is not a POST, see `retry_after_request`.
+    pub(super) fn send_request(
+        &self,
+        request: RequestBuilder,
+        data: JsonValue,
+        expected: StatusCode,
+    ) -> Result<String, String> {
+        let idempotent = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .is_some_and(|request| request.method() != Method::POST);
+        self.send_request_with_retries(request, data, expected, idempotent)
+    }
+
+    /// Same as `send_request`, for a POST that only reads, e.g. a GraphQL
+    /// query, so it is retried like a GET.
+    pub(super) fn send_query_request(
+        &self,
+        request: RequestBuilder,
+        data: JsonValue,
+        expected: StatusCode,
+    ) -> Result<String, String> {
+        self.send_request_with_retries(request, data, expected, true)
+    }
+
+    fn send_request_with_retries(
+        &self,
+        request: RequestBuilder,
+        data: JsonValue,
+        expected: StatusCode,
+        idempotent: bool,
+    ) -> Result<String, String> {
+        let request = if data.is_null() {
+            request
+        } else {
+            request.body(data.dump())
+        };
+
+        let mut attempt = 0;
+        loop {
+            let attempt_request = request
+                .try_clone()
+                .ok_or("Failed to send request: the request cannot be retried")?;
+
+            let (retry, error) = match attempt_request.send() {
+                Ok(response) if response.status() == expected => {
+                    return response
+                        .text()
+                        .map_err(|err| format!("Failed to read response: {}", err));
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry = retry_after_request(
+                        idempotent,
+                        Some(status),
+                        Some(response.headers()),
+                        attempt,
+                        time::now(),
+                    );
+                    let body = response.text().unwrap_or_default();
+                    (retry, format_status_error(status, &body))
+                }
+                Err(err) => (
+                    retry_after_request(idempotent, None, None, attempt, time::now()),
+                    format!("Failed to send request: {}", err),
+                ),
+            };
+
+            match retry {
+                Retry::After(delay) => {
+                    eprintln!("{}, retrying in {}s", error, delay.as_secs());
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Retry::No => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_retry_after_backoff() {
+        let secs = |attempt| match retry_after(
+            Some(StatusCode::BAD_GATEWAY),
+            None,
+            attempt,
+            0,
+        ) {
+            Retry::After(delay) => Some(delay.as_secs()),
+            Retry::No => None,
+        };
+        assert_eq!(Some(1), secs(0));
+        assert_eq!(Some(2), secs(1));
+        assert_eq!(Some(4), secs(2));
+        assert_eq!(None, secs(3));
+
+        assert_eq!(
+            Retry::After(Duration::from_secs(1)),
+            retry_after(None, None, 0, 0)
+        );
+        assert_eq!(Retry::No, retry_after(Some(StatusCode::NOT_FOUND), None, 0, 0));
+    }
+
+    #[test]
+    fn test_retry_after_request_not_idempotent() {
+        let bad_gateway = Some(StatusCode::BAD_GATEWAY);
+        assert_eq!(Retry::No, retry_after_request(false, bad_gateway, None, 0, 0));
+        assert_eq!(Retry::No, retry_after_request(false, None, None, 0, 0));
+        assert_eq!(
+            Retry::After(Duration::from_secs(1)),
+            retry_after_request(true, bad_gateway, None, 0, 0)
+        );
+
+        // the rate limit rejected the request, so it was not done.
+        let headers = create_headers(&[("retry-after", "30")]);
+        assert_eq!(
+            Retry::After(Duration::from_secs(30)),
+            retry_after_request(false, Some(StatusCode::FORBIDDEN), Some(&headers), 0, 0)
+        );
+        assert_eq!(
+            Retry::After(Duration::from_secs(1)),
+            retry_after_request(false, Some(StatusCode::TOO_MANY_REQUESTS), None, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_retry_after_rate_limit() {
+        let headers = create_headers(&[("retry-after", "30")]);
+        assert_eq!(
+            Retry::After(Duration::from_secs(30)),
+            retry_after(Some(StatusCode::FORBIDDEN), Some(&headers), 0, 0)
+        );
+
+        let headers = create_headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1060"),
+        ]);
+        assert_eq!(
+            Retry::After(Duration::from_secs(61)),
+            retry_after(Some(StatusCode::FORBIDDEN), Some(&headers), 0, 1000)
+        );
+        // Too long to wait.
+        assert_eq!(
+            Retry::No,
+            retry_after(Some(StatusCode::FORBIDDEN), Some(&headers), 0, 0)
+        );
+
+        // A missing permission is not retried.
+        let headers = create_headers(&[("x-ratelimit-remaining", "10")]);
+        assert_eq!(
+            Retry::No,
+            retry_after(Some(StatusCode::FORBIDDEN), Some(&headers), 0, 0)
+        );
+        assert_eq!(
+            Retry::After(Duration::from_secs(1)),
+            retry_after(Some(StatusCode::TOO_MANY_REQUESTS), Some(&headers), 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_format_status_error() {
+        assert_eq!(
+            "Failed to send request: 403 Forbidden: {\"message\":\"Resource not accessible by integration\"}",
+            format_status_error(
+                StatusCode::FORBIDDEN,
+                "{\"message\":\"Resource not accessible by integration\"}\n"
+            )
+        );
+        assert_eq!(
+            "Failed to send request: 502 Bad Gateway",
+            format_status_error(StatusCode::BAD_GATEWAY, "")
+        );
+        let body = "x".repeat(600);
+        assert!(format_status_error(StatusCode::BAD_GATEWAY, &body).ends_with("x..."));
+    }
+}
@@ -0,0 +1,210 @@
+//! Caches the GitHub users found by email, in-process and in a file,
+//! so that each run does not search every committer again.
+use json::{object, JsonValue};
+use std::{collections::HashMap, fs};
+
+use super::GithubUser;
+#[cfg(test)]
+use crate::time::now;
+
+const CACHE_VERSION: u32 = 1;
+
+/// The default time a cached user is used before it is searched again.
+pub const DEFAULT_TTL_HOURS: u64 = 7 * 24;
+
+/// A cached search result. A `None` user records that no GitHub user
+/// has the email, so it is not searched again until it expires.
+#[derive(Clone)]
+struct UserCacheRecord {
+    user: Option<GithubUser>,
+    fetched_at: u64,
+}
+
+/// Maps emails to the GitHub user found by the search, with a TTL.
+pub struct UserCache {
+    records: HashMap<String, UserCacheRecord>,
+    ttl_secs: u64,
+}
+
+impl UserCache {
+    pub fn new(ttl_hours: u64) -> UserCache {
+        UserCache {
+            records: HashMap::new(),
+            ttl_secs: ttl_hours * 60 * 60,
+        }
+    }
+
+    /// Returns `Some` with the cached search result if it has not expired,
+    /// or `None` if the email must be searched.
+    pub fn get(&self, email: &str, now: u64) -> Option<Option<GithubUser>> {
+        let record = self.records.get(email)?;
+        if now.saturating_sub(record.fetched_at) >= self.ttl_secs {
+            return None;
+        }
+        Some(record.user.clone())
+    }
+
+    pub fn store(&mut self, email: &str, user: &Option<GithubUser>, now: u64) {
+        let record = UserCacheRecord {
+            user: user.clone(),
+            fetched_at: now,
+        };
+        self.records.insert(email.to_string(), record);
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the cache as JSON, without the expired records.
+    pub fn to_json(&self, now: u64) -> JsonValue {
+        let mut users = JsonValue::new_object();
+        let mut emails: Vec<&String> = self.records.keys().collect();
+        emails.sort();
+        for email in emails {
+            let record = &self.records[email];
+            if now.saturating_sub(record.fetched_at) >= self.ttl_secs {
+                continue;
+            }
+            let mut value = match &record.user {
+                Some(user) => object! {
+                    "login" => user.username.as_str(),
+                    "avatar_url" => user.avatar_url.as_str(),
+                    "url" => user.url.as_str(),
+                },
+                None => object! { "login" => JsonValue::Null },
+            };
+            value["fetched_at"] = record.fetched_at.into();
+            users[email.as_str()] = value;
+        }
+        object! {
+            "version" => CACHE_VERSION,
+            "users" => users,
+        }
+    }
+
+    pub fn from_json(json: &JsonValue, ttl_hours: u64) -> Result<UserCache, String> {
+        if json["version"].as_u32() != Some(CACHE_VERSION) {
+            return Err(format!(
+                "Unsupported user cache version: {}",
+                json["version"]
+            ));
+        }
+
+        let mut cache = UserCache::new(ttl_hours);
+        for (email, value) in json["users"].entries() {
+            let fetched_at = value["fetched_at"]
+                .as_u64()
+                .ok_or(format!("Invalid fetched_at for {}", email))?;
+            let user = value["login"].as_str().map(|login| GithubUser {
+                username: login.to_string(),
+                avatar_url: value["avatar_url"].to_string(),
+                url: value["url"].to_string(),
+            });
+            cache.store(email, &user, fetched_at);
+        }
+        Ok(cache)
+    }
+
+    /// Loads the cache file. A missing file is an empty cache.
+    pub fn load_from_file(path: &str, ttl_hours: u64) -> Result<UserCache, String> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(UserCache::new(ttl_hours));
+            }
+            Err(err) => {
+                return Err(format!("Failed to read user cache {}: {}", path, err))
+            }
+        };
+        let json = json::parse(&content)
+            .map_err(|err| format!("Failed to parse user cache {}: {}", path, err))?;
+        UserCache::from_json(&json, ttl_hours)
+    }
+
+    pub fn write_to_file(&self, path: &str, now: u64) -> Result<(), String> {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir).map_err(|err| {
+                    format!("Failed to create directory {}: {}", dir.display(), err)
+                })?;
+            }
+        }
+        fs::write(path, self.to_json(now).pretty(2))
+            .map_err(|err| format!("Failed to write user cache {}: {}", path, err))
+    }
+}
+
+impl Default for UserCache {
+    fn default() -> Self {
+        UserCache::new(DEFAULT_TTL_HOURS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_user() -> Option<GithubUser> {
+        Some(GithubUser {
+            username: "user".to_string(),
+            avatar_url: "https://example.com/user.png".to_string(),
+            url: "https://github.com/user".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_user_cache_ttl() {
+        let mut cache = UserCache::new(1);
+        cache.store("user@example.com", &create_user(), 1000);
+        cache.store("nobody@example.com", &None, 1000);
+
+        let user = cache.get("user@example.com", 1000 + 3599).unwrap();
+        assert_eq!("user", user.unwrap().username);
+        assert!(cache.get("nobody@example.com", 1000).unwrap().is_none());
+        assert!(cache.get("user@example.com", 1000 + 3600).is_none());
+        assert!(cache.get("other@example.com", 1000).is_none());
+    }
+
+    #[test]
+    fn test_user_cache_json_roundtrip() {
+        let mut cache = UserCache::new(1);
+        cache.store("user@example.com", &create_user(), 1000);
+        cache.store("nobody@example.com", &None, 1000);
+        cache.store("expired@example.com", &None, 0);
+
+        let json = cache.to_json(4000);
+        assert_eq!(2, json["users"].len());
+        assert!(json["users"]["nobody@example.com"]["login"].is_null());
+
+        let cache = UserCache::from_json(&json, 1).unwrap();
+        assert_eq!(2, cache.len());
+        let user = cache.get("user@example.com", 4000).unwrap().unwrap();
+        assert_eq!("https://github.com/user", user.url);
+        assert!(cache.get("nobody@example.com", 4000).unwrap().is_none());
+
+        assert!(UserCache::from_json(&object! { "version" => 99 }, 1).is_err());
+    }
+
+    #[test]
+    fn test_user_cache_file() {
+        let dir = std::env::temp_dir().join("committer-coverage-user-cache-test");
+        let path = dir.join("users.json");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        assert!(UserCache::load_from_file(path, 1).unwrap().is_empty());
+
+        let mut cache = UserCache::new(1);
+        cache.store("user@example.com", &create_user(), now());
+        cache.write_to_file(path, now()).unwrap();
+
+        let cache = UserCache::load_from_file(path, 1).unwrap();
+        assert!(cache.get("user@example.com", now()).unwrap().is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Hands the analysis over from one job to another.
+//! On pull requests from forks the token of the `pull_request` job is
+//! read-only, so that job only analyzes and writes the handoff file as an
+//! artifact. A `workflow_run` or `pull_request_target` job then reads it and
+//! posts to the pull request, without running any code of the pull request.
+
+use crate::{analysis::CommitterCoverageSummary, report::json::Report};
+use json::{array, object, JsonValue};
+use std::fs;
+
+/// The version of the handoff file schema.
+pub const HANDOFF_VERSION: u32 = 1;
+
+/// The default path of the handoff file.
+pub const DEFAULT_FILE_NAME: &str = "committer-coverage-handoff.json";
+
+/// Represents the handoff file.
+///
+/// The schema is:
+/// ```json
+/// {
+///   "version": 1,
+///   "pull_request_number": 123,
+///   "report": { ... the JSON report ... },
+///   "lines": {
+///     "src/lib.rs": [[1, "8d5445550b1948b914853fc7f210ff3622ee0c18", "user@example.com", true]]
+///   }
+/// }
+/// ```
+/// The lines are the coverage lines with the commit and committer
+/// they are blamed to, e.g. to annotate the uncovered lines.
+pub struct Handoff {
+    pull_request_number: u32,
+    report: Report,
+}
+
+impl Handoff {
+    pub fn new(pull_request_number: u32, report: Report) -> Handoff {
+        Handoff {
+            pull_request_number,
+            report,
+        }
+    }
+
+    pub fn get_pull_request_number(&self) -> u32 {
+        self.pull_request_number
+    }
+
+    pub fn get_report(&self) -> &Report {
+        &self.report
+    }
+
+    pub fn get_summary(&self) -> &CommitterCoverageSummary {
+        self.report.get_summary()
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut lines = JsonValue::new_object();
+        for (path, file_lines) in self.get_summary().get_file_lines() {
+            let mut values = JsonValue::new_array();
+            for line in file_lines {
+                values
+                    .push(array![
+                        line.get_line(),
+                        line.get_commit(),
+                        line.get_email(),
+                        line.is_covered()
+                    ])
+                    .unwrap();
+            }
+            lines[path.as_str()] = values;
+        }
+
+        object! {
+            "version" => HANDOFF_VERSION,
+            "pull_request_number" => self.pull_request_number,
+            "report" => self.report.to_json(),
+            "lines" => lines,
+        }
+    }
+
+    /// Parses the handoff file.
+    /// The file comes from a job that may run code of a fork,
+    /// so anything unexpected is an error.
+    pub fn parse(content: &str) -> Result<Handoff, String> {
+        let json = json::parse(content)
+            .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+
+        if json["version"].as_u32() != Some(HANDOFF_VERSION) {
+            return Err(format!(
+                "Unsupported handoff version: {}",
+                json["version"]
+            ));
+        }
+        let pull_request_number = json["pull_request_number"]
+            .as_u32()
+            .ok_or("Missing pull_request_number in handoff")?;
+
+        let mut report = Report::from_json(&json["report"])?;
+        // the commits are used in requests with the token of the base repository.
+        if !is_commit_id(report.get_commit_sha()) {
+            return Err(format!(
+                "Invalid commit_sha in handoff: {}",
+                report.get_commit_sha()
+            ));
+        }
+        let summary = report.get_summary_mut();
+        for (path, lines) in json["lines"].entries() {
+            for line in lines.members() {
+                let (number, commit, email, covered) = (
+                    line[0].as_u32(),
+                    line[1].as_str(),
+                    line[2].as_str(),
+                    line[3].as_bool(),
+                );
+                match (number, commit, email, covered) {
+                    (Some(number), Some(commit), Some(email), Some(covered))
+                        if is_commit_id(commit) =>
+                    {
+                        summary.add_attributed_line(path, number, commit, email, covered)
+                    }
+                    _ => return Err(format!("Invalid line in handoff: {}", line.dump())),
+                }
+            }
+        }
+
+        Ok(Handoff {
+            pull_request_number,
+            report,
+        })
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_json().dump())
+            .map_err(|err| format!("Failed to write handoff {}: {}", path, err))
+    }
+
+    pub fn load_from_file(path: &str) -> Result<Handoff, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read handoff {}: {}", path, err))?;
+        Handoff::parse(&content)
+    }
+}
+
+/// Returns whether the value is a full commit id, 40 lowercase hex digits.
+fn is_commit_id(value: &str) -> bool {
+    value.len() == 40 && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT_A: &str = "8d5445550b1948b914853fc7f210ff3622ee0c18";
+    const COMMIT_B: &str = "5d2595a1368702ac796582016b764dedceabde85";
+
+    #[test]
+    fn test_handoff_roundtrip() {
+        let mut summary = CommitterCoverageSummary::default();
+        let email = "user@example.com";
+        summary.create_user_stat_if_not_exists(email, Some("User".to_string()));
+        summary.incr_user_line_cover(email, "src/lib.rs", true);
+        summary.add_attributed_line("src/lib.rs", 1, COMMIT_A, email, true);
+        summary.incr_user_line_cover(email, "src/lib.rs", false);
+        summary.add_attributed_line("src/lib.rs", 2, COMMIT_B, email, false);
+        let report = Report::new(summary, COMMIT_A, object! {});
+
+        let content = Handoff::new(12, report).to_json().dump();
+        let handoff = Handoff::parse(&content).expect("Failed to parse handoff");
+
+        assert_eq!(12, handoff.get_pull_request_number());
+        assert_eq!(COMMIT_A, handoff.get_report().get_commit_sha());
+        let summary = handoff.get_summary();
+        assert_eq!(2, summary.get_lines());
+        assert_eq!(1, summary.get_covered());
+        let lines = summary.get_file_lines().get("src/lib.rs").unwrap();
+        assert_eq!(2, lines.len());
+        assert_eq!(COMMIT_B, lines[1].get_commit());
+        assert!(!lines[1].is_covered());
+    }
+
+    #[test]
+    fn test_handoff_parse_errors() {
+        assert!(Handoff::parse("{}").is_err());
+        assert!(Handoff::parse(r#"{ "version": 1, "report": {} }"#).is_err());
+        let content = r#"{
+            "version": 1,
+            "pull_request_number": 1,
+            "report": {
+                "version": 1,
+                "commit_sha": "8d5445550b1948b914853fc7f210ff3622ee0c18",
+                "total": {},
+                "committers": []
+            },
+            "lines": { "src/lib.rs": [[1, "abc"]] }
+        }"#;
+        let err = Handoff::parse(content).err().unwrap();
+        assert!(err.starts_with("Invalid line in handoff"), "{}", err);
+    }
+
+    #[test]
+    fn test_handoff_parse_rejects_invalid_commits() {
+        let create_content = |commit_sha: &str, commit: &str| {
+            object! {
+                "version" => HANDOFF_VERSION,
+                "pull_request_number" => 1,
+                "report" => object! {
+                    "version" => 1,
+                    "commit_sha" => commit_sha,
+                    "total" => object! {},
+                    "committers" => array![],
+                },
+                "lines" => object! {
+                    "src/lib.rs" => array![array![1, commit, "user@example.com", false]],
+                },
+            }
+            .dump()
+        };
+        assert!(Handoff::parse(&create_content(COMMIT_A, COMMIT_B)).is_ok());
+
+        let crafted = "abc\") } } x: viewer { login";
+        let err = Handoff::parse(&create_content(COMMIT_A, crafted)).err().unwrap();
+        assert!(err.starts_with("Invalid line in handoff"), "{}", err);
+        let err = Handoff::parse(&create_content(crafted, COMMIT_B)).err().unwrap();
+        assert!(err.starts_with("Invalid commit_sha in handoff"), "{}", err);
+        assert!(Handoff::parse(&create_content(COMMIT_A, "8d54455")).is_err());
+        assert!(Handoff::parse(&create_content(&COMMIT_A.to_uppercase(), COMMIT_B)).is_err());
+    }
+}
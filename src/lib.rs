@@ -1,5 +1,9 @@
 pub mod analysis;
+pub mod baseline;
 pub mod config;
 pub mod coverage;
 pub mod git;
 pub mod github;
+pub mod handoff;
+pub mod report;
+pub mod time;
@@ -1,27 +1,257 @@
 // This is the main entry point of the program.
 use github_action_committer_coverage_stats::{
-    analysis::CommitterCoverageSummary, config::Config, coverage::Coverage,
-    git::Git, github, github::GitHubClient,
+    analysis::{AttributionOptions, CommitterCoverageSummary, ThresholdRules},
+    baseline::BaselineComparison,
+    config::{Config, Mode, ShallowClone},
+    coverage::Coverage,
+    git::{cache::BlameCache, Git},
+    github::{
+        app_auth::AppAuth, event::Event, user_cache::UserCache, GitHubClient,
+        UserProvider,
+    },
+    handoff::Handoff,
+    time,
+    report::{
+        csv, html,
+        json::Report,
+        markdown::{MarkdownWriter, SummaryContentOptions},
+        OutputFormat,
+    },
 };
+use std::env;
 
-fn print_summary_to_pr(
+const USAGE: &str = "Usage: committer-coverage analyze [options]
+
+Analyzes the code coverage per committer and prints the report to stdout.
+
+Options:
+    --coverage <files>            Comma separated coverage files (default: coverage.xml)
+    --repo <path>                 The git repository (default: .)
+    --config <path>               The config file (default: .committer-coverage.toml in the repo)
+    --format <markdown|json>      The output format (default: markdown)
+    --min-threshold <percent>     The minimum coverage per committer (default: 80)
+    --path-thresholds <rules>     Thresholds by path glob, e.g. src/core/**=90,cli/**=60
+    --committer-thresholds <rules>
+                                  Thresholds by committer email or name, e.g. a@example.com=60
+    --fail-below-threshold        Fail if any committer is below their threshold
+    --min-lines <n>               Committers with fewer lines get no verdict (default: 0)
+    --group-small-committers <bool>
+                                  Group committers below --min-lines into one row (default: true)
+    --sort-by <percent|lines|name>
+                                  The order of the committers (default: percent)
+    --directory-depth <n>         The depth of the per-directory breakdown (default: 1)
+    --details-max-files <n>       The worst-covered files listed per committer (default: 5)
+    --report-path <path>          Also write the JSON report to this path
+    --baseline-path <path>        Compare against the JSON report of an earlier run
+    --max-coverage-drop <points>  Fail if a committer's coverage drops by more than this
+    --csv-path <path>             Also write the CSV report to this path
+    --csv-format <csv|tsv>        The format of the CSV report (default: csv)
+    --csv-per-file                Write one CSV row per committer and file
+    --html-path <path>            Also write the HTML report to this path
+    --github-token <token>        Enables the GitHub integration, e.g. to link users
+    --github-repo <owner/name>    The GitHub repository
+    --github-app-id <id>          Authenticate as this GitHub App instead of with a token
+    --github-app-private-key-file <path>
+                                  The PEM private key of the GitHub App
+    --github-app-installation-id <id>
+                                  The installation of the GitHub App (default: the one on the repository)
+    --github-api-url <url>        The GitHub API URL (default: https://api.github.com)
+    --github-server-url <url>     The GitHub web URL (default: https://github.com)
+    --use-github-api-for-blame    Get the blame from the GitHub API instead of git
+    --since <date|duration|ref>   Only count lines changed since, e.g. 2024-01-01, 90d or v1.0
+    --half-life <duration>        Also report the coverage weighted by line age, e.g. 90d
+    --working-tree-author <identity>
+                                  Attribute uncommitted changes to this identity, e.g. Name <a@example.com>
+    --shallow-clone <fail|github_api|partial>
+                                  What to do if the repository is a shallow clone (default: fail)
+    --blame-cache-dir <path>      Cache the blame of unchanged files in this directory
+    --blame-threads <n>           The files blamed in parallel (default: CPUs, or 4 with the GitHub API)
+    --user-cache-path <path>      Cache the GitHub users found by email in this file
+    --user-cache-ttl-hours <n>    The hours a cached user is used (default: 168)
+";
+
+fn create_check_run(
     gh: &GitHubClient,
-    github_ref: &str,
+    config: &Config,
+    pull_request_number: u32,
     summary: &CommitterCoverageSummary,
-    min_threshold: f32,
 ) -> Result<(), String> {
-    let pull_request_number = match github::parse_pr_number_from_ref(github_ref)
-    {
+    gh.create_check_run(
+        pull_request_number,
+        config.get_check_run_name(),
+        summary,
+        config.get_threshold_rules(),
+        config.get_fail_below_threshold(),
+    )
+}
+
+/// Creates the commit status on the given head commit, or on the head of the
+/// pull request, or on the commit that triggered the workflow.
+fn create_commit_status(
+    gh: &GitHubClient,
+    config: &Config,
+    pull_request_number: Option<u32>,
+    head_sha: Option<String>,
+    summary: &CommitterCoverageSummary,
+) -> Result<(), String> {
+    let sha = match (head_sha, pull_request_number) {
+        (Some(sha), _) => sha,
+        (None, Some(pr)) => gh.get_pull_request_head_sha(pr)?,
+        (None, None) => config.get_github_sha().to_string(),
+    };
+
+    gh.create_commit_status(
+        &sha,
+        config.get_commit_status_context(),
+        summary,
+        config.get_threshold_rules(),
+        &config.get_github_run_url(),
+    )
+}
+
+/// Loads the event from the webhook payload, or from the ref if there is none.
+fn load_event(config: &Config) -> Event {
+    let name = config.get_github_event_name();
+    if !config.get_github_event_path().is_empty() {
+        match Event::load_from_file(name, config.get_github_event_path()) {
+            Ok(event) => return event,
+            Err(err) => eprintln!("{}, falling back to the ref", err),
+        }
+    }
+    Event::from_ref(name, config.get_github_ref_name())
+}
+
+/// Returns the pull request number to post to, if any.
+/// Pull requests from forks are skipped, the token is read-only there,
+/// and drafts if configured.
+fn get_pull_request_to_publish(config: &Config, event: &Event) -> Option<u32> {
+    let pr = match event.get_pull_request_to_publish() {
         Some(pr) => pr,
         None => {
+            eprintln!("Event {} is not a Pull Request", event.get_name());
+            return None;
+        }
+    };
+
+    if pr.is_draft() && config.get_skip_drafts() {
+        eprintln!("Pull Request {} is a draft, skipping", pr.get_number());
+        return None;
+    }
+    if is_read_only_fork(config, event) {
+        eprintln!(
+            "Pull Request {} is from a fork and the token is read-only, skipping. \
+             Use the analyze and publish modes to post to it.",
+            pr.get_number()
+        );
+        return None;
+    }
+    Some(pr.get_number())
+}
+
+/// Returns whether the event is a pull request from a fork whose token is
+/// read-only, nothing can be posted to it in the full mode.
+fn is_read_only_fork(config: &Config, event: &Event) -> bool {
+    let read_only = event.get_name() == "pull_request" && !config.has_github_app();
+    let fork = event
+        .get_pull_request()
+        .as_ref()
+        .is_some_and(|pr| pr.is_fork());
+    fork && read_only && config.get_mode() == Mode::Full
+}
+
+/// Checks that the pull request and the commit of the handoff are the ones
+/// of the event, the handoff file may come from a fork.
+fn check_handoff_pull_request(
+    gh: &GitHubClient,
+    event: &Event,
+    handoff: &Handoff,
+) -> Result<(), String> {
+    let pull_request_number = handoff.get_pull_request_number();
+    let commit_sha = handoff.get_report().get_commit_sha();
+    if let Some(pr) = event.get_pull_request() {
+        if pr.get_number() != pull_request_number {
             return Err(format!(
-                "Failed to parse pull request number from ref: {}",
-                github_ref
+                "The handoff is for Pull Request {}, but the event is for {}",
+                pull_request_number,
+                pr.get_number()
             ));
         }
+        return match event.get_head_sha() {
+            Some(head_sha) if head_sha == commit_sha => Ok(()),
+            Some(head_sha) => Err(format!(
+                "The handoff is for commit {}, but the head of Pull Request {} is {}",
+                commit_sha, pull_request_number, head_sha
+            )),
+            None => Err(format!(
+                "Cannot verify the handoff's commit for event {}",
+                event.get_name()
+            )),
+        };
+    }
+
+    let head_sha = match event.get_head_sha() {
+        Some(head_sha) => head_sha,
+        None => {
+            return Err(format!(
+                "Cannot verify the handoff's pull request for event {}",
+                event.get_name()
+            ))
+        }
     };
+    let pr_head_sha = gh.get_pull_request_head_sha(pull_request_number)?;
+    if &pr_head_sha != head_sha {
+        return Err(format!(
+            "The head of Pull Request {} is {}, but the workflow run is for {}",
+            pull_request_number, pr_head_sha, head_sha
+        ));
+    }
+    if commit_sha != head_sha {
+        return Err(format!(
+            "The handoff is for commit {}, but the workflow run is for {}",
+            commit_sha, head_sha
+        ));
+    }
+    Ok(())
+}
 
-    gh.print_summary_to_pr(pull_request_number, summary, min_threshold)
+/// Creates the GitHub client, authenticated as the GitHub App if configured,
+/// with the user cache loaded from the file. A broken cache file is ignored.
+fn create_github_client(config: &Config) -> Result<GitHubClient, String> {
+    let mut gh = GitHubClient::new(
+        config.get_github_api_url(),
+        config.get_github_repo(),
+        config.get_github_token(),
+    );
+
+    if config.has_github_app() {
+        gh.set_app_auth(AppAuth::new(
+            config.get_github_app_id(),
+            config.get_github_app_private_key(),
+            config.get_github_app_installation_id(),
+        )?);
+    }
+
+    let ttl_hours = config.get_user_cache_ttl_hours();
+    let user_cache = if config.get_user_cache_path().is_empty() {
+        Ok(UserCache::new(ttl_hours))
+    } else {
+        UserCache::load_from_file(config.get_user_cache_path(), ttl_hours)
+    };
+    match user_cache {
+        Ok(user_cache) => gh.set_user_cache(user_cache),
+        Err(err) => eprintln!("Ignoring the user cache: {}", err),
+    }
+    Ok(gh)
+}
+
+/// Writes the user cache for the next run, failing to do so is not an error.
+fn write_user_cache(gh: &GitHubClient, config: &Config) {
+    if config.get_user_cache_path().is_empty() {
+        return;
+    }
+    if let Err(err) = gh.write_user_cache_to_file(config.get_user_cache_path()) {
+        eprintln!("{}", err);
+    }
 }
 
 fn load_coverage_file(files: &[String]) -> Result<Coverage, String> {
@@ -35,30 +265,332 @@ fn load_coverage_file(files: &[String]) -> Result<Coverage, String> {
 
 fn calculate_summary_from_git_or_github_api(
     coverage: &Coverage,
-    use_github_api_for_blame: bool,
+    config: &Config,
     git: &Git,
-    gh: &GitHubClient,
+    gh: Option<&GitHubClient>,
 ) -> Result<CommitterCoverageSummary, String> {
-    if use_github_api_for_blame {
-        CommitterCoverageSummary::from_coverage_file_and_blame(coverage, gh)
-    } else {
-        CommitterCoverageSummary::from_coverage_file_and_blame(coverage, git)
+    let mut use_github_api_for_blame = config.get_use_github_api_for_blame();
+    let mut partial_history = false;
+    if !use_github_api_for_blame && git.is_shallow() {
+        match config.get_shallow_clone() {
+            ShallowClone::Fail => {
+                return Err(format!(
+                    "The repository {} is a shallow clone, its blame attributes \
+                     older lines to the oldest fetched commit. Fetch the full \
+                     history, e.g. with `fetch-depth: 0` in actions/checkout, or \
+                     set shallow_clone to github_api or partial.",
+                    git.get_path()
+                ))
+            }
+            ShallowClone::GithubApi => {
+                eprintln!("The repository is a shallow clone, blaming with the GitHub API");
+                use_github_api_for_blame = true;
+            }
+            ShallowClone::Partial => {
+                eprintln!("The repository is a shallow clone, the report is partial");
+                partial_history = true;
+            }
+        }
+    }
+
+    let now = time::now();
+    let since = match config.get_since() {
+        Some(since) => {
+            Some(since.resolve(now, |ref_name| git.get_commit_time(ref_name))?)
+        }
+        None => None,
+    };
+    let options = AttributionOptions {
+        since,
+        half_life: config.get_half_life(),
+        now: now as i64,
+        collect_lines: config.needs_attributed_lines(),
+    };
+
+    let threads = config.get_blame_threads_for(use_github_api_for_blame);
+    let mut summary = match (use_github_api_for_blame, gh) {
+        (true, Some(gh)) => {
+            if config.get_working_tree_author().is_some() {
+                eprintln!("The GitHub API only blames commits, working_tree_author is ignored");
+            }
+            CommitterCoverageSummary::from_coverage_file_and_parallel_blame(
+                coverage,
+                || Ok(gh),
+                threads,
+                &options,
+            )?
+        }
+        (true, None) => {
+            return Err("GitHub API for blame requires a GitHub token".to_string())
+        }
+        (false, _) => {
+            // a repository cannot be shared, each thread opens its own.
+            let path = config.get_workspace();
+            let cache_dir = config.get_blame_cache_dir();
+            let working_tree_author = config.get_working_tree_author();
+            let open_git = || {
+                let mut git = Git::reopen_from_path(path)?;
+                if !cache_dir.is_empty() {
+                    git.set_blame_cache(BlameCache::new(cache_dir));
+                }
+                if let Some(author) = &working_tree_author {
+                    git.set_working_tree_author(author.clone());
+                }
+                Ok(git)
+            };
+            CommitterCoverageSummary::from_coverage_file_and_parallel_blame(
+                coverage, open_git, threads, &options,
+            )?
+        }
+    };
+    summary.set_partial_history(partial_history);
+    Ok(summary)
+}
+
+/// Writes the reports that are configured with a path.
+fn write_reports(
+    config: &Config,
+    summary: &CommitterCoverageSummary,
+    commit_sha: &str,
+) -> Result<(), String> {
+    if !config.get_report_path().is_empty() {
+        Report::new(summary.clone(), commit_sha, config.to_json())
+            .write_to_file(config.get_report_path())?;
+    }
+
+    if !config.get_csv_path().is_empty() {
+        csv::write_to_file(
+            summary,
+            config.get_csv_path(),
+            config.get_csv_format(),
+            config.get_csv_per_file(),
+        )?;
+    }
+
+    if !config.get_html_path().is_empty() {
+        html::write_to_file(
+            summary,
+            config.get_workspace(),
+            config.get_html_path(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn create_summary_content_options(
+    config: &Config,
+    sha: &str,
+    comparison: &Option<BaselineComparison>,
+) -> SummaryContentOptions {
+    SummaryContentOptions {
+        thresholds: config.get_threshold_rules().clone(),
+        directory_depth: config.get_directory_depth(),
+        details_max_files: config.get_details_max_files(),
+        server_url: config.get_github_server_url().to_string(),
+        repo: config.get_github_repo().to_string(),
+        sha: sha.to_string(),
+        baseline: comparison.clone(),
+        sort_by: config.get_sort_by(),
+        group_small_committers: config.get_group_small_committers(),
+    }
+}
+
+/// Loads the baseline report and compares it with the summary.
+/// A missing baseline is not an error, e.g. on the first run. A baseline that
+/// cannot be loaded is an error if `max_coverage_drop` is set, since the gate
+/// would pass without a comparison.
+fn load_baseline_comparison(
+    config: &Config,
+    summary: &CommitterCoverageSummary,
+) -> Result<Option<BaselineComparison>, String> {
+    let baseline_path = config.get_baseline_path();
+    if baseline_path.is_empty() {
+        return Ok(None);
+    }
+    if !Report::exists(baseline_path) {
+        eprintln!("Baseline {} not found, skipping comparison", baseline_path);
+        return Ok(None);
+    }
+    match Report::load_from_file(baseline_path) {
+        Ok(baseline) => Ok(Some(BaselineComparison::new(
+            baseline.get_summary(),
+            summary,
+        ))),
+        Err(err) if config.get_max_coverage_drop().is_some() => {
+            Err(format!("Failed to load baseline for max_coverage_drop: {}", err))
+        }
+        Err(err) => {
+            eprintln!("Failed to load baseline, skipping comparison: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Returns an error if any committer's coverage dropped more than allowed.
+fn check_coverage_drop(
+    comparison: &Option<BaselineComparison>,
+    max_coverage_drop: Option<f32>,
+) -> Result<(), String> {
+    let (comparison, max_coverage_drop) = match (comparison, max_coverage_drop)
+    {
+        (Some(comparison), Some(max_coverage_drop)) => {
+            (comparison, max_coverage_drop)
+        }
+        _ => return Ok(()),
+    };
+
+    let regressions = comparison.get_regressions(max_coverage_drop);
+    if regressions.is_empty() {
+        return Ok(());
+    }
+
+    let regressions: Vec<String> = regressions
+        .iter()
+        .map(|(email, delta)| format!("{} ({:.2})", email, delta))
+        .collect();
+    Err(format!(
+        "Coverage dropped by more than {} points for: {}",
+        max_coverage_drop,
+        regressions.join(", ")
+    ))
+}
+
+/// Returns an error if any committer is below their threshold.
+fn check_thresholds(
+    summary: &CommitterCoverageSummary,
+    rules: &ThresholdRules,
+) -> Result<(), String> {
+    let mut failing: Vec<&str> = summary
+        .get_user_stats()
+        .values()
+        .filter(|user_stat| !rules.evaluate(user_stat).is_passed())
+        .map(|user_stat| user_stat.get_email())
+        .collect();
+    if failing.is_empty() {
+        return Ok(());
     }
+
+    failing.sort();
+    Err(format!(
+        "Coverage is below the threshold for: {}",
+        failing.join(", ")
+    ))
+}
+
+/// Runs the failure gates that are enabled in the config.
+fn check_gates(
+    config: &Config,
+    summary: &CommitterCoverageSummary,
+    comparison: &Option<BaselineComparison>,
+) -> Result<(), String> {
+    check_coverage_drop(comparison, config.get_max_coverage_drop())?;
+    if config.get_fail_below_threshold() {
+        check_thresholds(summary, config.get_threshold_rules())?;
+    }
+    Ok(())
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        run_action();
+    } else {
+        run_cli(&args);
+    }
+}
+
+/// Runs as GitHub Action, configured by the environment variables.
+fn run_action() {
     // panic if the config cannot be loaded
     let config = match Config::new_from_env() {
         Ok(config) => config,
         Err(err) => panic!("Problem loading config: {}", err),
     };
 
-    let gh = GitHubClient::new(
-        config.get_github_api_url(),
-        config.get_github_repo(),
-        config.get_github_token(),
-    );
+    let mut gh = create_github_client(&config)
+        .expect("Failed to create GitHub client");
+
+    let event = load_event(&config);
+    // blame the head of the pull request rather than the merge commit.
+    let head_sha = event
+        .get_head_sha()
+        .clone()
+        .unwrap_or(config.get_github_sha().to_string());
+    if !head_sha.is_empty() {
+        gh.set_blame_ref(&head_sha);
+    }
+
+    let (summary, sha, pull_request_number) = match config.get_mode() {
+        Mode::Publish => {
+            let handoff = Handoff::load_from_file(config.get_handoff_path())
+                .expect("Failed to load handoff");
+            check_handoff_pull_request(&gh, &event, &handoff)
+                .expect("Failed to check the pull request of the handoff");
+            (
+                handoff.get_summary().clone(),
+                handoff.get_report().get_commit_sha().to_string(),
+                Some(handoff.get_pull_request_number()),
+            )
+        }
+        Mode::Full | Mode::Analyze => {
+            let pull_request_number =
+                get_pull_request_to_publish(&config, &event);
+            // files are linked at the head of the pull request, like the blame.
+            (
+                analyze_action(&config, &gh, pull_request_number, &head_sha),
+                head_sha.clone(),
+                pull_request_number,
+            )
+        }
+    };
+
+    let comparison = match load_baseline_comparison(&config, &summary) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // the analyze mode leaves the posting to the publish mode, and the
+    // read-only token of a fork cannot post the commit status either.
+    if config.get_mode() != Mode::Analyze && !is_read_only_fork(&config, &event) {
+        // the status of a merge group or push is on its head commit.
+        let status_sha = match pull_request_number {
+            Some(_) if config.get_mode() == Mode::Publish => None,
+            _ => event.get_head_sha().clone(),
+        };
+        publish_action(
+            &config,
+            &gh,
+            &summary,
+            &sha,
+            &comparison,
+            pull_request_number,
+            status_sha,
+        );
+    }
 
+    write_user_cache(&gh, &config);
+
+    if let Err(err) = check_gates(&config, &summary, &comparison) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    println!("Success!");
+}
+
+/// Analyzes the coverage and writes the reports.
+/// In the analyze mode, the handoff file is written for the publish mode,
+/// with the head commit the files are linked at.
+fn analyze_action(
+    config: &Config,
+    gh: &GitHubClient,
+    pull_request_number: Option<u32>,
+    head_sha: &str,
+) -> CommitterCoverageSummary {
     let coverage = load_coverage_file(config.get_files())
         .expect("Failed to load coverage file");
 
@@ -67,24 +599,150 @@ fn main() {
 
     let summary = calculate_summary_from_git_or_github_api(
         &coverage,
-        config.get_use_github_api_for_blame(),
+        config,
         &git,
-        &gh,
+        Some(gh),
     )
     .expect("Failed to generate summary");
 
-    if config.get_github_event_name() == "pull_request" {
-        println!("Printing summary to Pull Request");
-        print_summary_to_pr(
-            &gh,
-            config.get_github_ref_name(),
-            &summary,
-            config.get_min_threshold(),
-        )
-        .expect("Failed to print summary to PR");
+    let commit_sha = git
+        .get_last_commit_hash()
+        .expect("Failed to get last commit hash");
+    write_reports(config, &summary, &commit_sha)
+        .expect("Failed to write reports");
+
+    if config.get_mode() == Mode::Analyze {
+        match pull_request_number {
+            Some(pr) => {
+                println!("Writing handoff to {}", config.get_handoff_path());
+                let report =
+                    Report::new(summary.clone(), head_sha, config.to_json());
+                Handoff::new(pr, report)
+                    .write_to_file(config.get_handoff_path())
+                    .expect("Failed to write handoff");
+            }
+            None => eprintln!(
+                "Event {} is not a Pull Request, not writing handoff",
+                config.get_github_event_name()
+            ),
+        }
+    }
+
+    summary
+}
+
+/// Posts the summary to the pull request, and the commit status if enabled.
+fn publish_action(
+    config: &Config,
+    gh: &GitHubClient,
+    summary: &CommitterCoverageSummary,
+    sha: &str,
+    comparison: &Option<BaselineComparison>,
+    pull_request_number: Option<u32>,
+    status_sha: Option<String>,
+) {
+    let options = create_summary_content_options(config, sha, comparison);
+
+    match pull_request_number {
+        Some(pr) => {
+            println!("Printing summary to Pull Request {}", pr);
+            gh.print_summary_to_pr(pr, summary, &options)
+                .expect("Failed to print summary to PR");
+
+            if config.get_check_run() {
+                println!("Creating check run");
+                create_check_run(gh, config, pr, summary)
+                    .expect("Failed to create check run");
+            }
+        }
+        None => eprintln!(
+            "Event {} is not a Pull Request",
+            config.get_github_event_name()
+        ),
+    }
+
+    if config.get_commit_status() {
+        println!("Creating commit status");
+        create_commit_status(gh, config, pull_request_number, status_sha, summary)
+            .expect("Failed to create commit status");
+    }
+}
+
+/// Runs from the command line, configured by the flags.
+/// The report is printed to stdout, everything else to stderr.
+fn run_cli(args: &[String]) {
+    let config = match args[0].as_str() {
+        "analyze" => Config::new_from_args(&args[1..]),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            return;
+        }
+        command => Err(format!("Unknown command: {}", command)),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = analyze(&config) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn analyze(config: &Config) -> Result<(), String> {
+    let gh = if config.has_github() {
+        Some(create_github_client(config)?)
     } else {
-        eprintln!("Event {} is not a Pull Request", config.get_github_event_name());
+        None
+    };
+
+    let coverage = load_coverage_file(config.get_files())?;
+    let git = Git::new_from_path(config.get_workspace())?;
+
+    let summary = calculate_summary_from_git_or_github_api(
+        &coverage,
+        config,
+        &git,
+        gh.as_ref(),
+    )?;
+
+    let commit_sha = git.get_last_commit_hash()?;
+    write_reports(config, &summary, &commit_sha)?;
+
+    let comparison = load_baseline_comparison(config, &summary)?;
+
+    match config.get_output_format() {
+        OutputFormat::Markdown => {
+            // files can only be linked if the GitHub repository is known.
+            let sha = if gh.is_some() { commit_sha.as_str() } else { "" };
+            let options =
+                create_summary_content_options(config, sha, &comparison);
+            if let Some(gh) = &gh {
+                let commits = summary.get_commits_by_email();
+                if let Err(err) = gh.resolve_users_by_commits(commits) {
+                    eprintln!("Failed to resolve users by commits: {}", err);
+                }
+            }
+            let users = gh.as_ref().map(|gh| gh as &dyn UserProvider);
+            println!(
+                "{}",
+                MarkdownWriter::new(users).create_summary_content(&summary, &options)
+            );
+        }
+        OutputFormat::Json => {
+            let report =
+                Report::new(summary.clone(), &commit_sha, config.to_json());
+            println!("{}", report.to_json().pretty(2));
+        }
     }
 
-    println!("Success!");
+    if let Some(gh) = &gh {
+        write_user_cache(gh, config);
+    }
+
+    check_gates(config, &summary, &comparison)
 }
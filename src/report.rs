@@ -0,0 +1,47 @@
+//! This module contains the report formats the analysis can be written to.
+
+pub mod csv;
+pub mod html;
+pub mod json;
+pub mod markdown;
+
+/// The format the report is printed in by the command line interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Markdown,
+    Json,
+}
+
+impl OutputFormat {
+    /// Parses the format name, i.e. `markdown` or `json`.
+    pub fn parse(format: &str) -> Result<OutputFormat, String> {
+        match format.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown format: {}", format)),
+        }
+    }
+}
+
+/// The order of the committers in the summary table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortBy {
+    /// The highest coverage percentage first.
+    Percent,
+    /// The most lines first.
+    Lines,
+    /// By name, or email if the name is unknown, alphabetically.
+    Name,
+}
+
+impl SortBy {
+    /// Parses the sort order name, i.e. `percent`, `lines` or `name`.
+    pub fn parse(sort_by: &str) -> Result<SortBy, String> {
+        match sort_by.to_lowercase().as_str() {
+            "percent" => Ok(SortBy::Percent),
+            "lines" => Ok(SortBy::Lines),
+            "name" => Ok(SortBy::Name),
+            _ => Err(format!("Unknown sort order: {}", sort_by)),
+        }
+    }
+}
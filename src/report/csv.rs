@@ -0,0 +1,202 @@
+//! CSV report
+//! This module writes the committer coverage summary as CSV or TSV,
+//! e.g. to paste into spreadsheets.
+//! Fields are quoted as described in RFC 4180.
+
+use crate::analysis::{CommitterCoverageSummary, CommitterCoverageUserStat};
+use std::fs;
+
+/// The delimited text format to write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    /// Parses the format name, i.e. `csv` or `tsv`.
+    pub fn parse(format: &str) -> Result<Format, String> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("Unknown format: {}", format)),
+        }
+    }
+
+    fn get_delimiter(&self) -> char {
+        match self {
+            Format::Csv => ',',
+            Format::Tsv => '\t',
+        }
+    }
+}
+
+/// Writes one row per committer, sorted by email.
+pub fn write_committers(
+    summary: &CommitterCoverageSummary,
+    format: Format,
+) -> String {
+    let mut content = String::new();
+    push_row(
+        &mut content,
+        format,
+        &["email", "name", "lines", "covered", "percent_covered"],
+    );
+
+    for user_stat in sort_user_stats(summary) {
+        push_row(
+            &mut content,
+            format,
+            &[
+                user_stat.get_email(),
+                user_stat.get_name().as_deref().unwrap_or(""),
+                &user_stat.get_lines().to_string(),
+                &user_stat.get_covered().to_string(),
+                &format!("{:.2}", user_stat.get_percent_covered()),
+            ],
+        );
+    }
+
+    content
+}
+
+/// Writes one row per committer and file (long format),
+/// sorted by email and then by path.
+pub fn write_committer_files(
+    summary: &CommitterCoverageSummary,
+    format: Format,
+) -> String {
+    let mut content = String::new();
+    push_row(
+        &mut content,
+        format,
+        &["email", "name", "path", "lines", "covered", "percent_covered"],
+    );
+
+    for user_stat in sort_user_stats(summary) {
+        for file_stat in user_stat.get_files().values() {
+            push_row(
+                &mut content,
+                format,
+                &[
+                    user_stat.get_email(),
+                    user_stat.get_name().as_deref().unwrap_or(""),
+                    file_stat.get_path(),
+                    &file_stat.get_lines().to_string(),
+                    &file_stat.get_covered().to_string(),
+                    &format!("{:.2}", file_stat.get_percent_covered()),
+                ],
+            );
+        }
+    }
+
+    content
+}
+
+/// Writes the report to the given path.
+/// If `per_file` is true, the long format with one row per committer and file is written.
+pub fn write_to_file(
+    summary: &CommitterCoverageSummary,
+    path: &str,
+    format: Format,
+    per_file: bool,
+) -> Result<(), String> {
+    let content = if per_file {
+        write_committer_files(summary, format)
+    } else {
+        write_committers(summary, format)
+    };
+    fs::write(path, content)
+        .map_err(|err| format!("Failed to write report {}: {}", path, err))
+}
+
+fn sort_user_stats(
+    summary: &CommitterCoverageSummary,
+) -> Vec<&CommitterCoverageUserStat> {
+    let mut user_stats: Vec<&CommitterCoverageUserStat> =
+        summary.get_user_stats().values().collect();
+    user_stats.sort_by(|a, b| a.get_email().cmp(b.get_email()));
+    user_stats
+}
+
+/// Appends the fields as a single row terminated by CRLF.
+fn push_row(content: &mut String, format: Format, fields: &[&str]) {
+    let delimiter = format.get_delimiter();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            content.push(delimiter);
+        }
+        content.push_str(&quote_field(field, delimiter));
+    }
+    content.push_str("\r\n");
+}
+
+/// Quotes the field if it contains the delimiter, a double quote or a line break.
+/// Double quotes inside the field are escaped by doubling them.
+fn quote_field(field: &str, delimiter: char) -> String {
+    let needs_quote = field
+        .chars()
+        .any(|c| c == delimiter || c == '"' || c == '\r' || c == '\n');
+    if needs_quote {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_summary() -> CommitterCoverageSummary {
+        let mut summary = CommitterCoverageSummary::default();
+        summary.create_user_stat_if_not_exists(
+            "user1@example.com",
+            Some("Doe, Jöhn \"JD\"".to_string()),
+        );
+        summary.incr_user_line_cover("user1@example.com", "src/lib.rs", true);
+        summary.incr_user_line_cover("user1@example.com", "src/main.rs", false);
+        summary.create_user_stat_if_not_exists("user0@example.com", None);
+        summary.incr_user_line_cover("user0@example.com", "src/lib.rs", true);
+        summary
+    }
+
+    #[test]
+    fn test_csv_write_committers() {
+        let content = write_committers(&create_summary(), Format::Csv);
+        assert_eq!(
+            "email,name,lines,covered,percent_covered\r\n\
+             user0@example.com,,1,1,100.00\r\n\
+             user1@example.com,\"Doe, Jöhn \"\"JD\"\"\",2,1,50.00\r\n",
+            content
+        );
+    }
+
+    #[test]
+    fn test_tsv_write_committer_files() {
+        let content = write_committer_files(&create_summary(), Format::Tsv);
+        assert_eq!(
+            "email\tname\tpath\tlines\tcovered\tpercent_covered\r\n\
+             user0@example.com\t\tsrc/lib.rs\t1\t1\t100.00\r\n\
+             user1@example.com\t\"Doe, Jöhn \"\"JD\"\"\"\tsrc/lib.rs\t1\t1\t100.00\r\n\
+             user1@example.com\t\"Doe, Jöhn \"\"JD\"\"\"\tsrc/main.rs\t1\t0\t0.00\r\n",
+            content
+        );
+    }
+
+    #[test]
+    fn test_quote_field() {
+        assert_eq!("plain", quote_field("plain", ','));
+        assert_eq!("\"a,b\"", quote_field("a,b", ','));
+        assert_eq!("a,b", quote_field("a,b", '\t'));
+        assert_eq!("\"a\tb\"", quote_field("a\tb", '\t'));
+        assert_eq!("\"line\nbreak\"", quote_field("line\nbreak", ','));
+    }
+
+    #[test]
+    fn test_format_parse() {
+        assert_eq!(Format::Csv, Format::parse("CSV").unwrap());
+        assert_eq!(Format::Tsv, Format::parse("tsv").unwrap());
+        assert!(Format::parse("xlsx").is_err());
+    }
+}
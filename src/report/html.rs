@@ -0,0 +1,223 @@
+//! HTML report
+//! This module writes a self-contained HTML report with the summary table
+//! and, for each file, the source lines colored by coverage with the
+//! committer each line is blamed to.
+
+use crate::analysis::{
+    AttributedLine, CommitterCoverageSummary, CommitterCoverageUserStat,
+};
+use std::{collections::BTreeMap, fs, path::Path};
+
+const STYLE: &str = "
+body { font-family: -apple-system, BlinkMacSystemFont, \"Segoe UI\", Helvetica, Arial, sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+table.summary td, table.summary th { border: 1px solid #d0d7de; padding: 4px 8px; }
+table.summary td.num { text-align: right; }
+table.source { width: 100%; font-family: ui-monospace, SFMono-Regular, Menlo, monospace; font-size: 12px; }
+table.source td { padding: 0 8px; vertical-align: top; white-space: pre; }
+table.source td.num { color: #6e7781; text-align: right; user-select: none; }
+table.source td.author { color: #6e7781; max-width: 16em; overflow: hidden; text-overflow: ellipsis; }
+tr.covered { background: #dafbe1; }
+tr.uncovered { background: #ffebe9; }
+details { margin: 0.5em 0; }
+summary { cursor: pointer; }
+";
+
+/// Creates the HTML report.
+/// The source of each file is read relative to the workspace directory.
+/// If a file cannot be read, only its coverage lines are shown.
+pub fn write_report(
+    summary: &CommitterCoverageSummary,
+    workspace: &str,
+) -> String {
+    let mut content = String::new();
+    content.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    content.push_str("<title>Committer Coverage Report</title>\n");
+    content.push_str(&format!("<style>{}</style>\n", STYLE));
+    content.push_str("</head>\n<body>\n");
+    content.push_str("<h1>Committer Coverage Report</h1>\n");
+    content.push_str(&format!(
+        "<p>Total coverage: {} / {} ({:.2}%)</p>\n",
+        summary.get_covered(),
+        summary.get_lines(),
+        summary.get_percent_covered()
+    ));
+    let unattributed = summary.get_unattributed();
+    if unattributed.get_lines() > 0 {
+        content.push_str(&format!(
+            "<p><strong>Unattributed:</strong> {} / {} ({:.2}%), lines without a blame \
+             that are not counted above.</p>\n",
+            unattributed.get_covered(),
+            unattributed.get_lines(),
+            unattributed.get_percent_covered()
+        ));
+    }
+    if summary.is_partial_history() {
+        content.push_str(
+            "<p><strong>Partial history:</strong> the repository is a shallow clone, \
+             lines may be attributed to the wrong committer.</p>\n",
+        );
+    }
+
+    content.push_str(&create_summary_table(summary));
+
+    content.push_str("<h2>Files</h2>\n");
+    for (path, lines) in summary.get_file_lines() {
+        content.push_str(&create_file_section(summary, workspace, path, lines));
+    }
+
+    content.push_str("</body>\n</html>\n");
+    content
+}
+
+/// Writes the HTML report to the given path.
+pub fn write_to_file(
+    summary: &CommitterCoverageSummary,
+    workspace: &str,
+    path: &str,
+) -> Result<(), String> {
+    fs::write(path, write_report(summary, workspace))
+        .map_err(|err| format!("Failed to write report {}: {}", path, err))
+}
+
+fn create_summary_table(summary: &CommitterCoverageSummary) -> String {
+    let mut user_stats: Vec<&CommitterCoverageUserStat> =
+        summary.get_user_stats().values().collect();
+    user_stats.sort_by(|a, b| {
+        b.get_percent_covered()
+            .partial_cmp(&a.get_percent_covered())
+            .unwrap()
+            .then(a.get_email().cmp(b.get_email()))
+    });
+
+    let mut table = String::new();
+    table.push_str("<table class=\"summary\">\n");
+    table.push_str("<tr><th>User</th><th>Email</th><th>Lines</th><th>Covered</th><th>% Covered</th></tr>\n");
+    for user_stat in user_stats {
+        table.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{:.2}</td></tr>\n",
+            escape(user_stat.get_name().as_deref().unwrap_or("")),
+            escape(user_stat.get_email()),
+            user_stat.get_lines(),
+            user_stat.get_covered(),
+            user_stat.get_percent_covered()
+        ));
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+fn create_file_section(
+    summary: &CommitterCoverageSummary,
+    workspace: &str,
+    path: &str,
+    lines: &[AttributedLine],
+) -> String {
+    let covered = lines.iter().filter(|line| line.is_covered()).count();
+    let percent = covered as f32 / lines.len() as f32 * 100.0;
+
+    let source = fs::read_to_string(Path::new(workspace).join(path))
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read source of {}: {}", path, err);
+            "".to_string()
+        });
+    let source: Vec<&str> = source.lines().collect();
+
+    let attributed: BTreeMap<u32, &AttributedLine> =
+        lines.iter().map(|line| (line.get_line(), line)).collect();
+    let last_line = attributed
+        .keys()
+        .last()
+        .copied()
+        .unwrap_or(0)
+        .max(source.len() as u32);
+
+    let mut section = String::new();
+    section.push_str(&format!(
+        "<details>\n<summary>{} ({} / {}, {:.2}%)</summary>\n<table class=\"source\">\n",
+        escape(path),
+        covered,
+        lines.len(),
+        percent
+    ));
+
+    for line_num in 1..=last_line {
+        let code = source.get(line_num as usize - 1).copied().unwrap_or("");
+        let (class, author) = match attributed.get(&line_num) {
+            Some(line) => (
+                if line.is_covered() { "covered" } else { "uncovered" },
+                create_author_display(summary, line.get_email()),
+            ),
+            None => ("", "".to_string()),
+        };
+        section.push_str(&format!(
+            "<tr class=\"{}\"><td class=\"num\">{}</td><td class=\"author\">{}</td><td>{}</td></tr>\n",
+            class,
+            line_num,
+            author,
+            escape(code)
+        ));
+    }
+
+    section.push_str("</table>\n</details>\n");
+    section
+}
+
+/// Shows the name of the committer, with the email as tooltip.
+fn create_author_display(
+    summary: &CommitterCoverageSummary,
+    email: &str,
+) -> String {
+    let name = summary
+        .get_user_stats()
+        .get(email)
+        .and_then(|stat| stat.get_name().clone())
+        .unwrap_or(email.to_string());
+    format!("<span title=\"{}\">{}</span>", escape(email), escape(&name))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_write_report() {
+        let mut summary = CommitterCoverageSummary::default();
+        let email = "user@example.com";
+        summary.create_user_stat_if_not_exists(email, Some("<User>".to_string()));
+        summary.incr_user_line_cover(email, "lib.rs", true);
+        summary.add_attributed_line("lib.rs", 1, "abc", email, true);
+        summary.incr_user_line_cover(email, "lib.rs", false);
+        summary.add_attributed_line("lib.rs", 3, "abc", email, false);
+
+        let content = write_report(&summary, "res/tests/html");
+
+        assert!(content.contains("<td>&lt;User&gt;</td><td>user@example.com</td>"));
+        assert!(content.contains("<summary>lib.rs (1 / 2, 50.00%)</summary>"));
+        assert!(content.contains(
+            "<tr class=\"covered\"><td class=\"num\">1</td><td class=\"author\"><span title=\"user@example.com\">&lt;User&gt;</span></td><td>pub fn add(a: u32, b: u32) -&gt; u32 {</td></tr>"
+        ));
+        assert!(content.contains("<tr class=\"uncovered\"><td class=\"num\">3</td>"));
+        assert!(content.contains("<tr class=\"\"><td class=\"num\">2</td><td class=\"author\"></td>"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("&lt;a href=&quot;x&quot;&gt;&amp;&#39;", escape("<a href=\"x\">&'"));
+    }
+}
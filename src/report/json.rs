@@ -0,0 +1,463 @@
+//! JSON report
+//! This module writes the full result of the analysis as a versioned JSON
+//! report so dashboards and other jobs can consume it, and parses it back,
+//! e.g. to use the report of an earlier run as a baseline.
+
+use crate::analysis::{
+    CommitterCoverageFileStat, CommitterCoverageSummary,
+    CommitterCoverageUserStat, WeightedCoverage,
+};
+use json::{object, JsonValue};
+use std::{fs, path::Path};
+
+/// The version of the JSON report schema.
+/// This is increased whenever the schema changes in an incompatible way.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The file name of the report when a directory is given instead of a file.
+pub const DEFAULT_FILE_NAME: &str = "committer-coverage.json";
+
+/// The version of this tool, written to the report.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Represents the JSON report.
+///
+/// The schema is:
+/// ```json
+/// {
+///   "version": 1,
+///   "tool_version": "0.1.0",
+///   "commit_sha": "8d5445550b1948b914853fc7f210ff3622ee0c18",
+///   "config": { "min_threshold": 80.0, ... },
+///   "total": {
+///     "lines": 100,
+///     "covered": 80,
+///     "percent_covered": 80.0,
+///     "partial_history": false,
+///     "unattributed": {
+///       "lines": 5,
+///       "covered": 2,
+///       "percent_covered": 40.0,
+///       "files": [
+///         { "path": "src/generated.rs", "lines": 5, "covered": 2, "percent_covered": 40.0 }
+///       ]
+///     }
+///   },
+///   "committers": [
+///     {
+///       "email": "user@example.com",
+///       "name": "User",
+///       "lines": 100,
+///       "covered": 80,
+///       "percent_covered": 80.0,
+///       "recency_weighted": { "lines": 42.5, "covered": 30.1, "percent_covered": 70.82 },
+///       "files": [
+///         { "path": "src/lib.rs", "lines": 100, "covered": 80, "percent_covered": 80.0 }
+///       ]
+///     }
+///   ]
+/// }
+/// ```
+/// `recency_weighted` is only present if the `half_life` option is set,
+/// also in `total`. The `unattributed` lines have no blame and are not in
+/// the totals.
+pub struct Report {
+    tool_version: String,
+    commit_sha: String,
+    config: JsonValue,
+    summary: CommitterCoverageSummary,
+}
+
+impl Report {
+    pub fn new(
+        summary: CommitterCoverageSummary,
+        commit_sha: &str,
+        config: JsonValue,
+    ) -> Report {
+        Report {
+            tool_version: TOOL_VERSION.to_string(),
+            commit_sha: commit_sha.to_string(),
+            config,
+            summary,
+        }
+    }
+
+    pub fn get_tool_version(&self) -> &str {
+        &self.tool_version
+    }
+
+    pub fn get_commit_sha(&self) -> &str {
+        &self.commit_sha
+    }
+
+    pub fn get_config(&self) -> &JsonValue {
+        &self.config
+    }
+
+    pub fn get_summary(&self) -> &CommitterCoverageSummary {
+        &self.summary
+    }
+
+    pub fn get_summary_mut(&mut self) -> &mut CommitterCoverageSummary {
+        &mut self.summary
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = object! {
+            "version" => SCHEMA_VERSION,
+            "tool_version" => self.tool_version.as_str(),
+            "commit_sha" => self.commit_sha.as_str(),
+            "config" => self.config.clone(),
+        };
+        let summary = self.summary.to_json();
+        json["total"] = summary["total"].clone();
+        json["committers"] = summary["committers"].clone();
+        json
+    }
+
+    /// Parses the JSON report.
+    /// Returns an error if the version is not supported.
+    pub fn parse(content: &str) -> Result<Report, String> {
+        let json = json::parse(content)
+            .map_err(|err| format!("Failed to parse JSON: {}", err))?;
+        Report::from_json(&json)
+    }
+
+    /// Parses the JSON report from a parsed value, e.g. when it is embedded.
+    /// Returns an error if the version is not supported.
+    pub fn from_json(json: &JsonValue) -> Result<Report, String> {
+        match json["version"].as_u32() {
+            Some(SCHEMA_VERSION) => {}
+            Some(version) => {
+                return Err(format!("Unsupported report version: {}", version))
+            }
+            None => return Err("Missing report version".to_string()),
+        }
+
+        let summary = CommitterCoverageSummary::from_json(json)?;
+
+        Ok(Report {
+            tool_version: json["tool_version"].as_str().unwrap_or("").to_string(),
+            commit_sha: json["commit_sha"].as_str().unwrap_or("").to_string(),
+            config: json["config"].clone(),
+            summary,
+        })
+    }
+
+    /// Writes the report to the given path.
+    /// If the path is a directory, the report is written as `committer-coverage.json` in it.
+    pub fn write_to_file(&self, path: &str) -> Result<(), String> {
+        let path = resolve_path(path);
+        fs::write(&path, self.to_json().pretty(2))
+            .map_err(|err| format!("Failed to write report {}: {}", path, err))
+    }
+
+    /// Returns whether there is a report at the given path, resolved like
+    /// `load_from_file`.
+    pub fn exists(path: &str) -> bool {
+        Path::new(&resolve_path(path)).is_file()
+    }
+
+    /// Loads the report from the given path.
+    /// If the path is a directory, the report is read from `committer-coverage.json` in it.
+    pub fn load_from_file(path: &str) -> Result<Report, String> {
+        let path = resolve_path(path);
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read report {}: {}", path, err))?;
+        Report::parse(&content)
+    }
+}
+
+/// Serialization of the summary to the `total` and `committers`
+/// fields of the report.
+impl CommitterCoverageSummary {
+    pub fn to_json(&self) -> JsonValue {
+        let mut user_stats: Vec<&CommitterCoverageUserStat> =
+            self.get_user_stats().values().collect();
+        user_stats.sort_by(|a, b| a.get_email().cmp(b.get_email()));
+
+        let mut committers = JsonValue::new_array();
+        for user_stat in user_stats {
+            committers.push(user_stat.to_json()).unwrap();
+        }
+
+        let mut unattributed_files = JsonValue::new_array();
+        for file_stat in self.get_unattributed_files().values() {
+            unattributed_files.push(file_stat_to_json(file_stat)).unwrap();
+        }
+        let mut unattributed = file_stat_to_json(&self.get_unattributed());
+        unattributed.remove("path");
+        unattributed["files"] = unattributed_files;
+
+        let mut total = object! {
+            "lines" => self.get_lines(),
+            "covered" => self.get_covered(),
+            "percent_covered" => round_percent(self.get_percent_covered()),
+            "partial_history" => self.is_partial_history(),
+            "unattributed" => unattributed,
+        };
+        if let Some(weighted) = self.get_recency_weighted() {
+            total["recency_weighted"] = weighted_to_json(weighted);
+        }
+
+        object! {
+            "total" => total,
+            "committers" => committers,
+        }
+    }
+
+    pub fn from_json(json: &JsonValue) -> Result<CommitterCoverageSummary, String> {
+        let committers = &json["committers"];
+        if !committers.is_array() {
+            return Err("Invalid report, committers is not an array".to_string());
+        }
+
+        let user_stats = committers
+            .members()
+            .map(CommitterCoverageUserStat::from_json)
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut summary = CommitterCoverageSummary::from_user_stats(user_stats);
+        // reports of older versions do not have it.
+        summary.set_partial_history(
+            json["total"]["partial_history"].as_bool().unwrap_or(false),
+        );
+        for file in json["total"]["unattributed"]["files"].members() {
+            summary.add_unattributed_file_stat(file_stat_from_json(file)?);
+        }
+        Ok(summary)
+    }
+}
+
+/// Serialization of the user stat to an entry of the `committers` field.
+impl CommitterCoverageUserStat {
+    pub fn to_json(&self) -> JsonValue {
+        let mut files = JsonValue::new_array();
+        for file_stat in self.get_files().values() {
+            files.push(file_stat_to_json(file_stat)).unwrap();
+        }
+
+        let mut json = object! {
+            "email" => self.get_email(),
+            "name" => self.get_name().clone(),
+            "lines" => self.get_lines(),
+            "covered" => self.get_covered(),
+            "percent_covered" => round_percent(self.get_percent_covered()),
+            "files" => files,
+        };
+        if let Some(weighted) = self.get_recency_weighted() {
+            json["recency_weighted"] = weighted_to_json(weighted);
+        }
+        json
+    }
+
+    pub fn from_json(json: &JsonValue) -> Result<CommitterCoverageUserStat, String> {
+        let email = json["email"]
+            .as_str()
+            .ok_or("Invalid report, committer has no email")?;
+        let name = json["name"].as_str().map(|name| name.to_string());
+        let lines = json["lines"]
+            .as_u32()
+            .ok_or(format!("Invalid report, no lines for {}", email))?;
+        let covered = json["covered"]
+            .as_u32()
+            .ok_or(format!("Invalid report, no covered for {}", email))?;
+
+        let mut user_stat =
+            CommitterCoverageUserStat::new(email, name, lines, covered);
+        for file in json["files"].members() {
+            user_stat.add_file_stat(file_stat_from_json(file)?);
+        }
+        user_stat.set_recency_weighted(weighted_from_json(&json["recency_weighted"]));
+
+        Ok(user_stat)
+    }
+}
+
+/// The weights are rounded to 4 decimals, the percentage to 2.
+fn weighted_to_json(weighted: &WeightedCoverage) -> JsonValue {
+    let round = |value: f64| (value * 10000.0).round() / 10000.0;
+    object! {
+        "lines" => round(weighted.get_lines()),
+        "covered" => round(weighted.get_covered()),
+        "percent_covered" => round_percent(weighted.get_percent_covered()),
+    }
+}
+
+fn weighted_from_json(json: &JsonValue) -> Option<WeightedCoverage> {
+    let lines = json["lines"].as_f64()?;
+    let covered = json["covered"].as_f64()?;
+    Some(WeightedCoverage::new(lines, covered))
+}
+
+fn file_stat_to_json(file_stat: &CommitterCoverageFileStat) -> JsonValue {
+    object! {
+        "path" => file_stat.get_path(),
+        "lines" => file_stat.get_lines(),
+        "covered" => file_stat.get_covered(),
+        "percent_covered" => round_percent(file_stat.get_percent_covered()),
+    }
+}
+
+fn file_stat_from_json(
+    json: &JsonValue,
+) -> Result<CommitterCoverageFileStat, String> {
+    let path = json["path"]
+        .as_str()
+        .ok_or("Invalid report, file has no path")?;
+    let lines = json["lines"]
+        .as_u32()
+        .ok_or(format!("Invalid report, no lines for {}", path))?;
+    let covered = json["covered"]
+        .as_u32()
+        .ok_or(format!("Invalid report, no covered for {}", path))?;
+    Ok(CommitterCoverageFileStat::new(path, lines, covered))
+}
+
+/// Rounds the percentage to 2 decimals, so the report does not contain
+/// floating point noise like `66.66666412353516`.
+fn round_percent(percent: f32) -> f64 {
+    (percent as f64 * 100.0).round() / 100.0
+}
+
+fn resolve_path(path: &str) -> String {
+    let p = Path::new(path);
+    if p.is_dir() {
+        p.join(DEFAULT_FILE_NAME).to_string_lossy().to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_summary() -> CommitterCoverageSummary {
+        let mut summary = CommitterCoverageSummary::default();
+        summary.create_user_stat_if_not_exists(
+            "user1@example.com",
+            Some("User, One".to_string()),
+        );
+        summary.incr_user_line_cover("user1@example.com", "src/lib.rs", true);
+        summary.incr_user_line_cover("user1@example.com", "src/main.rs", false);
+        summary.incr_user_line_cover("user1@example.com", "src/main.rs", false);
+        summary.create_user_stat_if_not_exists("user2@example.com", None);
+        summary.incr_user_line_cover("user2@example.com", "src/lib.rs", true);
+        summary
+    }
+
+    #[test]
+    fn test_json_report_round_trip() {
+        let config = object! { "min_threshold" => 80.0 };
+        let report = Report::new(create_summary(), "abc123", config);
+
+        let content = report.to_json().dump();
+        let parsed = Report::parse(&content).expect("Failed to parse report");
+
+        assert_eq!(TOOL_VERSION, parsed.get_tool_version());
+        assert_eq!("abc123", parsed.get_commit_sha());
+        assert_eq!(80.0, parsed.get_config()["min_threshold"].as_f32().unwrap());
+
+        let summary = parsed.get_summary();
+        assert_eq!(4, summary.get_lines());
+        assert_eq!(2, summary.get_covered());
+
+        let user1 = summary.get_user_stats().get("user1@example.com").unwrap();
+        assert_eq!(&Some("User, One".to_string()), user1.get_name());
+        assert_eq!(3, user1.get_lines());
+        let file = user1.get_files().get("src/main.rs").unwrap();
+        assert_eq!(2, file.get_lines());
+        assert_eq!(0, file.get_covered());
+
+        let user2 = summary.get_user_stats().get("user2@example.com").unwrap();
+        assert_eq!(&None, user2.get_name());
+
+        // serializing the parsed report gives the same JSON.
+        assert_eq!(content, parsed.to_json().dump());
+    }
+
+    #[test]
+    fn test_json_report_partial_history() {
+        let mut summary = create_summary();
+        summary.set_partial_history(true);
+        let content = Report::new(summary, "abc123", object! {}).to_json().dump();
+        let parsed = Report::parse(&content).expect("Failed to parse report");
+        assert!(parsed.get_summary().is_partial_history());
+
+        // older reports do not have it.
+        let content = content.replace("\"partial_history\":true,", "");
+        let parsed = Report::parse(&content).expect("Failed to parse report");
+        assert!(!parsed.get_summary().is_partial_history());
+    }
+
+    #[test]
+    fn test_json_report_recency_weighted() {
+        let content = Report::new(create_summary(), "abc123", object! {}).to_json().dump();
+        assert!(!content.contains("recency_weighted"));
+
+        let mut user = CommitterCoverageUserStat::new("user@example.com", None, 4, 2);
+        user.set_recency_weighted(Some(WeightedCoverage::new(3.0, 1.0)));
+        let summary = CommitterCoverageSummary::from_user_stats(vec![user]);
+        let json = Report::new(summary, "abc123", object! {}).to_json();
+        assert_eq!(
+            r#"{"lines":3,"covered":1,"percent_covered":33.33}"#,
+            json["committers"][0]["recency_weighted"].dump()
+        );
+
+        let parsed = Report::parse(&json.dump()).expect("Failed to parse report");
+        let summary = parsed.get_summary();
+        assert_eq!(&Some(WeightedCoverage::new(3.0, 1.0)), summary.get_recency_weighted());
+        let user = summary.get_user_stats().get("user@example.com").unwrap();
+        assert_eq!(&Some(WeightedCoverage::new(3.0, 1.0)), user.get_recency_weighted());
+    }
+
+    #[test]
+    fn test_json_report_unattributed() {
+        let mut summary = create_summary();
+        summary.incr_unattributed_line("src/generated.rs", true);
+        summary.incr_unattributed_line("src/generated.rs", false);
+        let json = Report::new(summary, "abc123", object! {}).to_json();
+        assert_eq!(
+            r#"{"lines":2,"covered":1,"percent_covered":50,"files":[{"path":"src/generated.rs","lines":2,"covered":1,"percent_covered":50}]}"#,
+            json["total"]["unattributed"].dump()
+        );
+        // the unattributed lines are not in the totals.
+        assert_eq!(4, json["total"]["lines"].as_u32().unwrap());
+
+        let content = json.dump();
+        let parsed = Report::parse(&content).expect("Failed to parse report");
+        assert_eq!(2, parsed.get_summary().get_unattributed().get_lines());
+        assert_eq!(content, parsed.to_json().dump());
+    }
+
+    #[test]
+    fn test_json_report_rounds_percent() {
+        let json = create_summary().to_json();
+        assert_eq!(
+            "33.33",
+            json["committers"][0]["percent_covered"].dump()
+        );
+    }
+
+    #[test]
+    fn test_json_report_exists() {
+        let dir = std::env::temp_dir().join("committer-coverage-test-report-exists");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir_path = dir.to_str().unwrap();
+        assert!(!Report::exists(dir_path));
+
+        fs::write(dir.join(DEFAULT_FILE_NAME), "not json").unwrap();
+        assert!(Report::exists(dir_path));
+        assert!(Report::load_from_file(dir_path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_report_parse_unsupported_version() {
+        let result = Report::parse(r#"{"version": 999, "committers": []}"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,675 @@
+//! Markdown report
+//! This module writes the committer coverage summary as Markdown,
+//! e.g. to be posted as a comment to the pull request.
+
+use crate::{
+    analysis::{
+        CommitterCoverageFileStat, CommitterCoverageSummary,
+        CommitterCoverageUserStat, ThresholdRules, WeightedCoverage,
+    },
+    baseline::{self, BaselineComparison},
+    github::UserProvider,
+    report::SortBy,
+};
+use email_address::EmailAddress;
+
+/// Shown below the total when the blame only saw part of the history.
+const PARTIAL_HISTORY_WARNING: &str = "> [!WARNING]
+> Partial history: the repository is a shallow clone, so lines may be \
+attributed to the wrong committer. Check out with `fetch-depth: 0`.
+
+";
+
+/// Options that control how the summary content is rendered.
+#[derive(Clone)]
+pub struct SummaryContentOptions {
+    /// Users below their threshold are marked as failing.
+    pub thresholds: ThresholdRules,
+    /// The number of directory components shown in the per-committer details.
+    pub directory_depth: usize,
+    /// The maximum number of worst-covered files listed per committer.
+    pub details_max_files: usize,
+    /// The GitHub web URL, used to link files, e.g. `https://github.com`.
+    pub server_url: String,
+    /// The repository the files are linked to, e.g. `owner/repo`.
+    pub repo: String,
+    /// The commit the files are linked to. Files are not linked if empty.
+    pub sha: String,
+    /// The comparison against the baseline report, if one was loaded.
+    pub baseline: Option<BaselineComparison>,
+    /// The order of the committers in the table.
+    pub sort_by: SortBy,
+    /// Whether committers below the minimum lines of the thresholds are
+    /// grouped into a single "others" row instead of shown without a verdict.
+    pub group_small_committers: bool,
+}
+
+impl Default for SummaryContentOptions {
+    fn default() -> Self {
+        SummaryContentOptions {
+            thresholds: ThresholdRules::new(80.0),
+            directory_depth: 1,
+            details_max_files: 5,
+            server_url: "https://github.com".to_string(),
+            repo: "".to_string(),
+            sha: "".to_string(),
+            baseline: None,
+            sort_by: SortBy::Percent,
+            group_small_committers: true,
+        }
+    }
+}
+
+/// Writes the summary content as Markdown.
+/// Users are linked to their GitHub profile if a user provider is given.
+pub struct MarkdownWriter<'a> {
+    users: Option<&'a dyn UserProvider>,
+}
+
+impl<'a> MarkdownWriter<'a> {
+    pub fn new(users: Option<&'a dyn UserProvider>) -> MarkdownWriter<'a> {
+        MarkdownWriter { users }
+    }
+}
+
+/// Implementation for summary content, since it's so long.
+impl MarkdownWriter<'_> {
+    pub fn create_summary_content(
+        &self,
+        summary: &CommitterCoverageSummary,
+        options: &SummaryContentOptions,
+    ) -> String {
+        let mut content = String::new();
+        let header = self.create_summary_content_header(summary, options);
+        content.push_str(header.as_str());
+
+        let table = self.create_summary_content_table(summary, options);
+        content.push_str(table.as_str());
+
+        let details = self.create_summary_content_details(summary, options);
+        content.push_str(details.as_str());
+
+        let footer = "\n⭐ [github-action-committer-coverage-stats](https://github.com/petrabarus/github-action-committer-coverage-stats)";
+        content.push_str(footer);
+
+        content
+    }
+
+    fn create_summary_content_header(
+        &self,
+        summary: &CommitterCoverageSummary,
+        options: &SummaryContentOptions,
+    ) -> String {
+        let mut header = String::new();
+        header.push_str("# Committer Coverage Report\n");
+        let delta = match &options.baseline {
+            Some(comparison) => format!(
+                ", {} vs baseline",
+                baseline::format_delta(comparison.get_total_delta())
+            ),
+            None => "".to_string(),
+        };
+        header.push_str(&format!(
+            "Total coverage: {} / {} ({:.2}%{})\n\n",
+            summary.get_covered(),
+            summary.get_lines(),
+            summary.get_percent_covered(),
+            delta
+        ));
+        let unattributed = summary.get_unattributed();
+        if unattributed.get_lines() > 0 {
+            header.push_str(&format!(
+                "Unattributed: {} / {} ({:.2}%), lines without a blame that are not counted above\n\n",
+                unattributed.get_covered(),
+                unattributed.get_lines(),
+                unattributed.get_percent_covered()
+            ));
+        }
+        if summary.is_partial_history() {
+            header.push_str(PARTIAL_HISTORY_WARNING);
+        }
+        header
+    }
+
+    fn create_summary_content_table(
+        &self,
+        summary: &CommitterCoverageSummary,
+        options: &SummaryContentOptions,
+    ) -> String {
+        let mut table = String::new();
+        // the recency-weighted coverage is only shown if it was computed.
+        let weighted = summary.get_recency_weighted().is_some();
+        table.push_str("|  | **User** | **Lines** | **Covered** | **% Covered** |");
+        if weighted {
+            table.push_str(" **% Recency-weighted** |");
+        }
+        if options.baseline.is_some() {
+            table.push_str(" **Δ** |");
+        }
+        table.push_str("\n|--|------|-------:|---------:|-----------|");
+        if weighted {
+            table.push_str("---------:|");
+        }
+        if options.baseline.is_some() {
+            table.push_str("-----:|");
+        }
+        table.push('\n');
+
+        let mut others = CommitterCoverageFileStat::new("", 0, 0);
+        let mut others_weighted = WeightedCoverage::default();
+        let mut others_count = 0;
+
+        for user_stat in self.sort_user_stats(summary, options.sort_by) {
+            if self.is_grouped_into_others(&user_stat, options) {
+                others = CommitterCoverageFileStat::new(
+                    "",
+                    others.get_lines() + user_stat.get_lines(),
+                    others.get_covered() + user_stat.get_covered(),
+                );
+                if let Some(user_weighted) = user_stat.get_recency_weighted() {
+                    others_weighted = WeightedCoverage::new(
+                        others_weighted.get_lines() + user_weighted.get_lines(),
+                        others_weighted.get_covered() + user_weighted.get_covered(),
+                    );
+                }
+                others_count += 1;
+                continue;
+            }
+
+            let decision = options.thresholds.evaluate(&user_stat);
+            let status = if decision.is_skipped() {
+                "➖"
+            } else if decision.is_passed() {
+                "✅"
+            } else {
+                "❌"
+            };
+
+            let user =
+                self.create_summary_content_table_row_user_display(&user_stat);
+
+            table.push_str(&format!(
+                "| {} | {} | {} | {:.2} {} |",
+                user,
+                user_stat.get_lines(),
+                user_stat.get_covered(),
+                user_stat.get_percent_covered(),
+                status
+            ));
+            if weighted {
+                // committers without a weighted line have no weighted coverage.
+                let weighted = user_stat.get_recency_weighted().unwrap_or_default();
+                table.push_str(&format!(" {:.2} |", weighted.get_percent_covered()));
+            }
+            if let Some(comparison) = &options.baseline {
+                let delta = match comparison.get_user_delta(user_stat.get_email()) {
+                    Some(delta) => baseline::format_delta(delta),
+                    None => "new".to_string(),
+                };
+                table.push_str(&format!(" {} |", delta));
+            }
+            table.push('\n');
+        }
+
+        if others_count > 0 {
+            table.push_str(&format!(
+                "|  | others ({} with fewer than {} lines) | {} | {} | {:.2} ➖ |",
+                others_count,
+                options.thresholds.get_min_lines(),
+                others.get_lines(),
+                others.get_covered(),
+                others.get_percent_covered()
+            ));
+            if weighted {
+                table.push_str(&format!(" {:.2} |", others_weighted.get_percent_covered()));
+            }
+            if options.baseline.is_some() {
+                table.push_str("  |");
+            }
+            table.push('\n');
+        }
+
+        table
+    }
+
+    fn is_grouped_into_others(
+        &self,
+        user_stat: &CommitterCoverageUserStat,
+        options: &SummaryContentOptions,
+    ) -> bool {
+        options.group_small_committers
+            && options.thresholds.is_below_min_lines(user_stat)
+    }
+
+    fn sort_user_stats(
+        &self,
+        summary: &CommitterCoverageSummary,
+        sort_by: SortBy,
+    ) -> Vec<CommitterCoverageUserStat> {
+        let mut sorted_user_stats: Vec<CommitterCoverageUserStat> =
+            summary.get_user_stats().values().cloned().collect();
+
+        sorted_user_stats.sort_by(|a, b| {
+            let order = match sort_by {
+                SortBy::Percent => b
+                    .get_percent_covered()
+                    .partial_cmp(&a.get_percent_covered())
+                    .unwrap(),
+                SortBy::Lines => b.get_lines().cmp(&a.get_lines()),
+                SortBy::Name => self
+                    .must_get_name_or_email(a)
+                    .to_lowercase()
+                    .cmp(&self.must_get_name_or_email(b).to_lowercase()),
+            };
+            order.then(a.get_email().cmp(b.get_email()))
+        });
+
+        sorted_user_stats
+    }
+
+    /// Creates a collapsible section for each committer listing the
+    /// coverage per directory and the worst-covered files.
+    fn create_summary_content_details(
+        &self,
+        summary: &CommitterCoverageSummary,
+        options: &SummaryContentOptions,
+    ) -> String {
+        let mut details = String::new();
+
+        for user_stat in self.sort_user_stats(summary, options.sort_by) {
+            if user_stat.get_files().is_empty()
+                || self.is_grouped_into_others(&user_stat, options)
+            {
+                continue;
+            }
+
+            details.push_str(&format!(
+                "\n<details>\n<summary>{} ({} files, {:.2}%)</summary>\n\n",
+                self.must_get_name_or_email(&user_stat),
+                user_stat.get_files().len(),
+                user_stat.get_percent_covered()
+            ));
+
+            for failure in options.thresholds.evaluate(&user_stat).get_failures() {
+                let scope = match failure.scope.as_str() {
+                    "*" => "".to_string(),
+                    scope => format!(" in `{}`", scope),
+                };
+                details.push_str(&format!(
+                    "❌ {:.2}% covered{}, below the threshold of {}%\n\n",
+                    failure.percent_covered, scope, failure.threshold
+                ));
+            }
+
+            details.push_str(
+                "| **Directory** | **Lines** | **Covered** | **% Covered** |
+|------|-------:|---------:|---------:|
+",
+            );
+            for dir_stat in user_stat
+                .get_directory_stats(options.directory_depth)
+                .values()
+            {
+                details.push_str(&self.create_summary_content_details_row(
+                    dir_stat.get_path(),
+                    dir_stat,
+                ));
+            }
+
+            let worst_files =
+                user_stat.get_worst_covered_files(options.details_max_files);
+            if !worst_files.is_empty() {
+                details.push_str(
+                    "\n| **Worst-covered File** | **Lines** | **Covered** | **% Covered** |
+|------|-------:|---------:|---------:|
+",
+                );
+                for file_stat in worst_files {
+                    let file = self.create_file_link(file_stat.get_path(), options);
+                    details.push_str(
+                        &self.create_summary_content_details_row(&file, file_stat),
+                    );
+                }
+            }
+
+            details.push_str("\n</details>\n");
+        }
+
+        details.push_str(&self.create_summary_content_details_unattributed(summary, options));
+        details
+    }
+
+    /// Creates a collapsible section listing the files with the most
+    /// unattributed lines, or nothing if all lines were attributed.
+    fn create_summary_content_details_unattributed(
+        &self,
+        summary: &CommitterCoverageSummary,
+        options: &SummaryContentOptions,
+    ) -> String {
+        let files = summary.get_unattributed_files();
+        if files.is_empty() {
+            return String::new();
+        }
+
+        let mut details = format!(
+            "\n<details>\n<summary>Unattributed ({} files, {:.2}%)</summary>\n\n",
+            files.len(),
+            summary.get_unattributed().get_percent_covered()
+        );
+        details.push_str(
+            "| **File** | **Lines** | **Covered** | **% Covered** |
+|------|-------:|---------:|---------:|
+",
+        );
+        let mut files: Vec<&CommitterCoverageFileStat> = files.values().collect();
+        files.sort_by_key(|file| std::cmp::Reverse(file.get_lines()));
+        files.truncate(options.details_max_files);
+        for file_stat in files {
+            let file = self.create_file_link(file_stat.get_path(), options);
+            details.push_str(&self.create_summary_content_details_row(&file, file_stat));
+        }
+        details.push_str("\n</details>\n");
+        details
+    }
+
+    fn create_summary_content_details_row(
+        &self,
+        label: &str,
+        stat: &CommitterCoverageFileStat,
+    ) -> String {
+        format!(
+            "| {} | {} | {} | {:.2} |\n",
+            label,
+            stat.get_lines(),
+            stat.get_covered(),
+            stat.get_percent_covered()
+        )
+    }
+
+    /// Creates a markdown link to the file blob at the configured commit.
+    /// Returns the plain path if no commit is configured.
+    fn create_file_link(
+        &self,
+        path: &str,
+        options: &SummaryContentOptions,
+    ) -> String {
+        if options.sha.is_empty() {
+            return path.to_string();
+        }
+        let path = path.trim_start_matches("./");
+        format!(
+            "[{}]({}/{}/blob/{}/{})",
+            path, options.server_url, options.repo, options.sha, path
+        )
+    }
+
+    fn must_get_name_or_email(
+        &self,
+        user_stat: &CommitterCoverageUserStat,
+    ) -> String {
+        match user_stat.get_name() {
+            Some(name) => name.to_string(),
+            None => user_stat.get_email().to_string(),
+        }
+    }
+
+    fn create_summary_content_table_row_user_display(
+        &self,
+        user_stat: &CommitterCoverageUserStat,
+    ) -> String {
+        let email = user_stat.get_email();
+        let name = user_stat.get_name();
+
+        if !EmailAddress::is_valid(email) {
+            eprintln!("Invalid email: {}", email);
+            return self.create_unknown_user_display(name);
+        }
+
+        let users = match self.users {
+            Some(users) => users,
+            None => return self.create_unknown_user_display(name),
+        };
+
+        match users.get_user_by_email(email) {
+            Ok(user) => match user {
+                Some(user) => self.create_user_display(
+                    user.username.as_str(),
+                    user.url.as_str(),
+                    user.avatar_url.as_str(),
+                ),
+                None => {
+                    eprintln!("Received None user when creating summary table");
+                    self.create_unknown_user_display(name)
+                }
+            },
+            Err(err) => {
+                eprintln!("Failed to get user by email, got error when creating summary table: {}", err);
+                self.create_unknown_user_display(name)
+            }
+        }
+    }
+
+    fn create_unknown_user_display(&self, name: &Option<String>) -> String {
+        self.create_user_display(
+            self.must_get_name(name).as_str(),
+            "https://github.com", 
+            "https://avatars.githubusercontent.com/u/1234567890?v=4"
+        )
+    }
+
+    fn create_user_display(&self, name: &str, url: &str, avatar_url: &str) -> String {
+        format!(
+            "<a href=\"{}\"><img src=\"{}\" width=\"20\"/></a> | {}",
+            url, avatar_url, name
+        )
+    }
+
+    fn must_get_name(&self, name: &Option<String>) -> String {
+        if let Some(name) = name {
+            name.to_string()
+        } else {
+            eprintln!("Email and name are both invalid");
+            "unknown".to_string()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{GithubUser, MockUserProvider};
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_table() {
+        let mut users = MockUserProvider::new();
+        users.expect_get_user_by_email().returning(|_| {
+            Ok(Some(GithubUser {
+                username: "user".to_string(),
+                avatar_url: "https://avatars.githubusercontent.com/u/1".to_string(),
+                url: "https://github.com/user".to_string(),
+            }))
+        });
+        let writer = MarkdownWriter::new(Some(&users));
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user@example.com", None, 10, 9),
+            CommitterCoverageUserStat::new("invalid", Some("Other".to_string()), 10, 5),
+        ]);
+
+        let table = writer.create_summary_content_table(
+            &summary,
+            &SummaryContentOptions::default(),
+        );
+
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(4, rows.len());
+        assert_eq!(
+            "| <a href=\"https://github.com/user\"><img src=\"https://avatars.githubusercontent.com/u/1\" width=\"20\"/></a> | user | 10 | 9 | 90.00 ✅ |",
+            rows[2]
+        );
+        assert!(rows[3].ends_with("| Other | 10 | 5 | 50.00 ❌ |"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_table_min_lines_and_sort() {
+        let writer = MarkdownWriter::new(None);
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("a@example.com", Some("Bob".to_string()), 100, 50),
+            CommitterCoverageUserStat::new("b@example.com", Some("alice".to_string()), 200, 190),
+            CommitterCoverageUserStat::new("c@example.com", Some("Carol".to_string()), 2, 0),
+            CommitterCoverageUserStat::new("d@example.com", Some("Dave".to_string()), 3, 1),
+        ]);
+        let mut thresholds = ThresholdRules::new(80.0);
+        thresholds.set_min_lines(10);
+
+        let options = SummaryContentOptions {
+            thresholds,
+            sort_by: SortBy::Name,
+            ..Default::default()
+        };
+        let table = writer.create_summary_content_table(&summary, &options);
+        let rows: Vec<&str> = table.lines().skip(2).collect();
+        assert_eq!(3, rows.len());
+        assert!(rows[0].ends_with("| alice | 200 | 190 | 95.00 ✅ |"));
+        assert!(rows[1].ends_with("| Bob | 100 | 50 | 50.00 ❌ |"));
+        assert_eq!("|  | others (2 with fewer than 10 lines) | 5 | 1 | 20.00 ➖ |", rows[2]);
+
+        let options = SummaryContentOptions {
+            group_small_committers: false,
+            sort_by: SortBy::Lines,
+            ..options
+        };
+        let table = writer.create_summary_content_table(&summary, &options);
+        let rows: Vec<&str> = table.lines().skip(2).collect();
+        assert_eq!(4, rows.len());
+        assert!(rows[0].ends_with("| alice | 200 | 190 | 95.00 ✅ |"));
+        assert!(rows[3].ends_with("| Carol | 2 | 0 | 0.00 ➖ |"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_table_without_users() {
+        let writer = MarkdownWriter::new(None);
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new(
+                "user@example.com",
+                Some("User".to_string()),
+                10,
+                9,
+            ),
+        ]);
+
+        let table = writer.create_summary_content_table(
+            &summary,
+            &SummaryContentOptions::default(),
+        );
+        assert!(table.contains("| User | 10 | 9 | 90.00 ✅ |"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_table_recency_weighted() {
+        let writer = MarkdownWriter::new(None);
+        let mut user = CommitterCoverageUserStat::new(
+            "user@example.com",
+            Some("User".to_string()),
+            10,
+            9,
+        );
+        user.set_recency_weighted(Some(WeightedCoverage::new(4.0, 3.0)));
+        let summary = CommitterCoverageSummary::from_user_stats(vec![user]);
+
+        let table = writer.create_summary_content_table(
+            &summary,
+            &SummaryContentOptions::default(),
+        );
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            "|  | **User** | **Lines** | **Covered** | **% Covered** | **% Recency-weighted** |",
+            rows[0]
+        );
+        assert_eq!("|--|------|-------:|---------:|-----------|---------:|", rows[1]);
+        assert!(rows[2].ends_with("| User | 10 | 9 | 90.00 ✅ | 75.00 |"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_header_with_baseline() {
+        let writer = MarkdownWriter::new(None);
+        let baseline = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user@example.com", None, 10, 5),
+        ]);
+        let summary = CommitterCoverageSummary::from_user_stats(vec![
+            CommitterCoverageUserStat::new("user@example.com", None, 10, 6),
+        ]);
+
+        let options = SummaryContentOptions {
+            baseline: Some(BaselineComparison::new(&baseline, &summary)),
+            ..Default::default()
+        };
+        let header = writer.create_summary_content_header(&summary, &options);
+        assert!(header.contains("Total coverage: 6 / 10 (60.00%, ▲ 10.00 vs baseline)"));
+        assert!(!header.contains("Partial history"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_header_partial_history() {
+        let writer = MarkdownWriter::new(None);
+        let mut summary = CommitterCoverageSummary::default();
+        summary.set_partial_history(true);
+        let header =
+            writer.create_summary_content_header(&summary, &Default::default());
+        assert!(header.contains("> Partial history: the repository is a shallow clone"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_unattributed() {
+        let writer = MarkdownWriter::new(None);
+        let mut summary = CommitterCoverageSummary::default();
+        let options = SummaryContentOptions {
+            details_max_files: 1,
+            ..Default::default()
+        };
+        let content = writer.create_summary_content(&summary, &options);
+        assert!(!content.contains("Unattributed"));
+
+        summary.incr_unattributed_line("src/generated.rs", true);
+        summary.incr_unattributed_line("src/generated.rs", false);
+        summary.incr_unattributed_line("src/stale.rs", false);
+        let content = writer.create_summary_content(&summary, &options);
+        assert!(content.contains(
+            "Unattributed: 1 / 3 (33.33%), lines without a blame that are not counted above"
+        ));
+        assert!(content.contains("<summary>Unattributed (2 files, 33.33%)</summary>"));
+        assert!(content.contains("| src/generated.rs | 2 | 1 | 50.00 |"));
+        assert!(!content.contains("| src/stale.rs |"));
+    }
+
+    #[test]
+    fn test_markdownwriter_create_summary_content_details() {
+        let writer = MarkdownWriter::new(None);
+        let mut summary = CommitterCoverageSummary::default();
+        let email = "user@example.com";
+        summary.create_user_stat_if_not_exists(email, Some("User".to_string()));
+        summary.incr_user_line_cover(email, "src/lib.rs", true);
+        summary.incr_user_line_cover(email, "src/main.rs", false);
+
+        let mut thresholds = ThresholdRules::new(40.0);
+        thresholds.add_path_threshold("src/main.rs", 60.0).unwrap();
+        let options = SummaryContentOptions {
+            thresholds,
+            repo: "owner/repo".to_string(),
+            sha: "abc123".to_string(),
+            ..Default::default()
+        };
+        let details = writer.create_summary_content_details(&summary, &options);
+
+        assert!(details.contains("<summary>User (2 files, 50.00%)</summary>"));
+        assert!(details.contains("| src | 2 | 1 | 50.00 |"));
+        assert!(details.contains(
+            "| [src/main.rs](https://github.com/owner/repo/blob/abc123/src/main.rs) | 1 | 0 | 0.00 |"
+        ));
+        assert!(!details.contains("[src/lib.rs]"));
+        assert!(details.contains(
+            "❌ 0.00% covered in `src/main.rs`, below the threshold of 60%"
+        ));
+    }
+
+}
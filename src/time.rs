@@ -0,0 +1,184 @@
+//! Helpers for timestamps in seconds since the epoch.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the current time in seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses an RFC 3339 timestamp, e.g. `2024-01-31T12:00:00Z` or
+/// `2024-01-31T12:00:00.123+07:00`, to seconds since the epoch.
+pub fn parse_datetime(value: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid timestamp: {}", value);
+
+    let (date, time) = value.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+    let days = parse_date_to_days(date).ok_or_else(invalid)?;
+
+    // split the offset from the time, e.g. 12:00:00.123+07:00.
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let index = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (time, offset) = time.split_at(index);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+        let hours: i64 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+        (time, sign * (hours * 3600 + minutes * 60))
+    };
+
+    // fractions of a second are ignored.
+    let time = time.split('.').next().unwrap_or(time);
+    let parts: Vec<i64> = time
+        .split(':')
+        .map(|part| part.parse::<i64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    if parts.len() != 3 || parts[0] > 23 || parts[1] > 59 || parts[2] > 60 {
+        return Err(invalid());
+    }
+
+    Ok(days * 86400 + parts[0] * 3600 + parts[1] * 60 + parts[2] - offset)
+}
+
+/// Parses a `YYYY-MM-DD` date to seconds since the epoch, at midnight UTC.
+pub fn parse_date(value: &str) -> Result<i64, String> {
+    parse_date_to_days(value)
+        .map(|days| days * 86400)
+        .ok_or(format!("Invalid date: {}", value))
+}
+
+/// Parses a duration in days, weeks or hours, e.g. `90d`, `2w` or `12h`,
+/// to seconds.
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid duration: {}", value);
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let seconds = match unit {
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = value[..value.len() - 1].parse().map_err(|_| invalid())?;
+    count.checked_mul(seconds).ok_or_else(invalid)
+}
+
+/// The start of the time window of the lines that are counted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Since {
+    /// A time in seconds since the epoch, from a date or a timestamp.
+    At(i64),
+    /// A duration in seconds before now.
+    Ago(u64),
+    /// The time of the commit of a git ref, e.g. a tag of the last release.
+    Ref(String),
+}
+
+impl Since {
+    /// Parses a duration like `90d`, a date like `2024-01-01`, a timestamp,
+    /// or else takes the value as a git ref.
+    pub fn parse(value: &str) -> Result<Since, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Empty since".to_string());
+        }
+        if let Ok(duration) = parse_duration(value) {
+            return Ok(Since::Ago(duration));
+        }
+        if let Ok(time) = parse_date(value).or_else(|_| parse_datetime(value)) {
+            return Ok(Since::At(time));
+        }
+        Ok(Since::Ref(value.to_string()))
+    }
+
+    /// Returns the time in seconds since the epoch,
+    /// a ref is resolved to the time of its commit.
+    pub fn resolve<F: Fn(&str) -> Result<i64, String>>(
+        &self,
+        now: u64,
+        resolve_ref: F,
+    ) -> Result<i64, String> {
+        match self {
+            Since::At(time) => Ok(*time),
+            Since::Ago(duration) => i64::try_from(now)
+                .ok()
+                .zip(i64::try_from(*duration).ok())
+                .and_then(|(now, duration)| now.checked_sub(duration))
+                .ok_or_else(|| format!("Invalid duration: {}s", duration)),
+            Since::Ref(name) => resolve_ref(name),
+        }
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date to days since the epoch.
+fn parse_date_to_days(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // see: https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(Ok(0), parse_datetime("1970-01-01T00:00:00Z"));
+        assert_eq!(Ok(1468275250), parse_datetime("2016-07-11T22:14:10Z"));
+        assert_eq!(Ok(1709208000), parse_datetime("2024-02-29T12:00:00.5Z"));
+        assert_eq!(Ok(1709208000), parse_datetime("2024-02-29T19:00:00+07:00"));
+        assert_eq!(Ok(1709208000), parse_datetime("2024-02-29T07:30:00-04:30"));
+        assert!(parse_datetime("2024-02-29").is_err());
+        assert!(parse_datetime("2024-13-01T00:00:00Z").is_err());
+        assert!(parse_datetime("2024-01-01T25:00:00Z").is_err());
+    }
+
+    #[test]
+    fn test_parse_date_and_duration() {
+        assert_eq!(Ok(1709164800), parse_date("2024-02-29"));
+        assert!(parse_date("2024-02").is_err());
+        assert_eq!(Ok(90 * 86400), parse_duration("90d"));
+        assert_eq!(Ok(2 * 7 * 86400), parse_duration("2w"));
+        assert_eq!(Ok(12 * 3600), parse_duration("12h"));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("18446744073709551615w").is_err());
+    }
+
+    #[test]
+    fn test_since() {
+        assert_eq!(Ok(Since::Ago(90 * 86400)), Since::parse("90d"));
+        assert_eq!(Ok(Since::At(1709164800)), Since::parse("2024-02-29"));
+        assert_eq!(Ok(Since::At(1709208000)), Since::parse("2024-02-29T12:00:00Z"));
+        assert_eq!(Ok(Since::Ref("v1.0".to_string())), Since::parse(" v1.0 "));
+        assert!(Since::parse("").is_err());
+
+        let resolve_ref = |name: &str| match name {
+            "v1.0" => Ok(1000),
+            _ => Err(format!("Unknown ref {}", name)),
+        };
+        assert_eq!(Ok(500), Since::At(500).resolve(2000, resolve_ref));
+        assert_eq!(Ok(1900), Since::Ago(100).resolve(2000, resolve_ref));
+        assert_eq!(Ok(1000), Since::Ref("v1.0".to_string()).resolve(2000, resolve_ref));
+        assert!(Since::Ref("main".to_string()).resolve(2000, resolve_ref).is_err());
+        assert!(Since::Ago(u64::MAX).resolve(2000, resolve_ref).is_err());
+        assert!(Since::Ago(i64::MAX as u64).resolve(u64::MAX, resolve_ref).is_err());
+    }
+}
@@ -3,6 +3,7 @@ mod tests {
     use github_action_committer_coverage_stats::analysis::*;
     use github_action_committer_coverage_stats::git::BlameProvider;
     use github_action_committer_coverage_stats::github::*;
+    use github_action_committer_coverage_stats::report::markdown::SummaryContentOptions;
 
     fn create_client() -> GitHubClient {
         GitHubClient::new(
@@ -21,8 +22,8 @@ mod tests {
         summary.create_user_stat_if_not_exists("user@example.com", None);
         summary.set_user_stat("user@example.com", 100, 50).expect("User does not exist");
 
-        let min_threshold = 80.0;
-        let res = client.print_summary_to_pr(1, &summary, min_threshold);
+        let options = SummaryContentOptions::default();
+        let res = client.print_summary_to_pr(1, &summary, &options);
         println!("{:?}", res);
         assert!(res.is_ok());
     }